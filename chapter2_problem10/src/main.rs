@@ -1,301 +1,202 @@
-extern crate rand;
+extern crate hmm;
 extern crate regex;
 
 use std::env;
-use std::fs::File;
-use std::io::Read;
+use std::fs;
 use std::path::Path;
+use std::process;
+use std::sync::Arc;
 use std::time::SystemTime;
 
-use rand::Rng;
+use hmm::{
+    parse_positional_arg, parse_training_args, text_to_observations, HmmError, HmmModel, InformationCriterion, TrainingConfig, TrainingObserver, LETTERS_AND_SPACE, TRAINING_FLAGS_USAGE,
+};
 use regex::Regex;
 
-#[derive(Debug)]
-pub struct HmmModel {
-    pub state_transition_matrix: Box<[Box<[f64]>]>,
-    pub observation_probability_matrix: Box<[Box<[f64]>]>,
-    pub initial_state_distribution_vector: Box<[f64]>,
-    alpha_matrix: Box<[Box<[f64]>]>,
-    beta_matrix: Box<[Box<[f64]>]>,
-    gamma_matrix: Box<[Box<[f64]>]>,
-    di_gamma_tensor: Box<[Box<[Box<[f64]>]>]>,
-    scale_factors: Box<[f64]>,
+fn main() {
+    if let Err(error) = run() {
+        println!("Error: {}", error);
+        process::exit(1);
+    }
 }
 
-impl HmmModel {
-    pub fn train_model(number_of_hidden_state_symbols: usize, number_of_observation_symbols: usize, observations: &Box<[usize]>) -> HmmModel {
-        let min_initial_value = 30.0;
-        let max_initial_value = 70.0;
-        let min_iterations = 100;
-        let max_iterations = 100;
-        let improvement_threshold = 0.01;
-
-        let mut model = HmmModel {
-            state_transition_matrix: vec![
-                vec![0.0;
-                     number_of_hidden_state_symbols
-                ].into_boxed_slice();
-                number_of_hidden_state_symbols
-            ].into_boxed_slice(),
-            observation_probability_matrix: vec![
-                vec![0.0;
-                     number_of_observation_symbols
-                ].into_boxed_slice();
-                number_of_hidden_state_symbols
-            ].into_boxed_slice(),
-            initial_state_distribution_vector: vec![0.0; number_of_hidden_state_symbols].into_boxed_slice(),
-            alpha_matrix: vec![
-                vec![0.0;
-                     number_of_hidden_state_symbols
-                ].into_boxed_slice();
-                observations.len()
-            ].into_boxed_slice(),
-            beta_matrix: vec![
-                vec![0.0;
-                     number_of_hidden_state_symbols
-                ].into_boxed_slice();
-                observations.len()
-            ].into_boxed_slice(),
-            gamma_matrix: vec![
-                vec![0.0;
-                     number_of_hidden_state_symbols
-                ].into_boxed_slice();
-                observations.len()
-            ].into_boxed_slice(),
-            di_gamma_tensor: vec![
-                vec![
-                    vec![0.0;
-                         number_of_hidden_state_symbols
-                    ].into_boxed_slice();
-                    number_of_hidden_state_symbols
-                ].into_boxed_slice();
-                observations.len()
-            ].into_boxed_slice(),
-            scale_factors: vec![0.0; observations.len()].into_boxed_slice(),
-        };
-
-        // Generate guesses
-        let mut rng = rand::thread_rng();
-        for i in 0..number_of_hidden_state_symbols {
-            for j in 0..number_of_hidden_state_symbols {
-                model.state_transition_matrix[i][j] = rng.gen_range(min_initial_value, max_initial_value);
-            }
-
-            for j in 0..number_of_observation_symbols {
-                model.observation_probability_matrix[i][j] = rng.gen_range(min_initial_value, max_initial_value);
-            }
-
-            model.initial_state_distribution_vector[i] = rng.gen_range(min_initial_value, max_initial_value);
-        }
-
-        // Normalize
-        for i in 0..number_of_hidden_state_symbols {
-            let state_transition_row_sum = model.state_transition_matrix[i].iter().sum::<f64>();
-            model.state_transition_matrix[i] = model.state_transition_matrix[i].iter().map(|&probability| probability / state_transition_row_sum).collect();
+fn run() -> Result<(), HmmError> {
+    let number_of_observation_symbols = 27;
+    let symbols = LETTERS_AND_SPACE;
 
-            let observation_probability_row_sum = model.observation_probability_matrix[i].iter().sum::<f64>();
-            model.observation_probability_matrix[i] = model.observation_probability_matrix[i].iter().map(|&probability| probability / observation_probability_row_sum).collect();
+    let args: Box<[String]> = env::args().collect();
+    let split_into_paragraphs = args.iter().any(|arg| arg == "--paragraphs");
+    let args: Box<[String]> = args.iter().filter(|&arg| arg != "--paragraphs").cloned().collect();
+    let (number_of_restarts, criterion, args) = take_model_selection_flags(&args).map_err(print_usage)?;
+    let mut default_training_config = TrainingConfig::default();
+    if number_of_restarts > 1 {
+        // Restarts train in parallel, so their iterations would interleave
+        default_training_config = default_training_config.observer(Arc::new(RestartPrinter));
+    }
+    let (training_config, args) = parse_training_args(&args, default_training_config).map_err(print_usage)?;
+    if args.len() != 3 && args.len() != 4 {
+        return Err(print_usage(HmmError::Parse("Incorrect command arguments".to_string())));
+    }
+    // Either one number of hidden states or a range of them to choose from, e.g. 2-6
+    let (min_number_of_hidden_state_symbols, max_number_of_hidden_state_symbols) = match args[1].split_once('-') {
+        Some((min, max)) => (parse_positional_arg("number_of_hidden_state_symbols", min)?, parse_positional_arg("number_of_hidden_state_symbols", max)?),
+        None => {
+            let number_of_hidden_state_symbols = parse_positional_arg("number_of_hidden_state_symbols", &args[1])?;
+            (number_of_hidden_state_symbols, number_of_hidden_state_symbols)
         }
-        let initial_state_distribution_vector_sum = model.initial_state_distribution_vector.iter().sum::<f64>();
-        model.initial_state_distribution_vector = model.initial_state_distribution_vector.iter().map(|&probability| probability / initial_state_distribution_vector_sum).collect();
-
-        let mut iterations = 0;
-        let mut log_probability = std::f64::NEG_INFINITY;
-        let mut old_log_probability = std::f64::NEG_INFINITY;
-
-        while iterations < min_iterations || (log_probability - old_log_probability).abs() > improvement_threshold && iterations < max_iterations {
-            old_log_probability = log_probability;
-
-            model.populate_alpha_matrix_and_scale_factors(&observations);
-            model.populate_beta_matrix(&observations);
-            model.compute_gamma_matrix_and_di_gamma_tensor(&observations);
-
-            model.initial_state_distribution_vector = model.gamma_matrix[0].clone();
-            for i in 0..number_of_hidden_state_symbols {
-                for j in 0..number_of_hidden_state_symbols {
-                    let numerator = model.di_gamma_tensor[..model.di_gamma_tensor.len() - 1]
-                        .iter()
-                        .map(|di_gamma_matrix| di_gamma_matrix[i][j])
-                        .sum::<f64>();
-                    let denominator = model.gamma_matrix[..model.gamma_matrix.len() - 1]
-                        .iter()
-                        .map(|gamma_box| gamma_box[i])
-                        .sum::<f64>();
-
-                    model.state_transition_matrix[i][j] = numerator / denominator;
-                }
-
-                for j in 0..number_of_observation_symbols {
-                    let mut numerator = 0.0;
-                    let mut denominator = 0.0;
-                    for observation_index in 0..(observations.len() - 1) {
-                        if observations[observation_index] == j {
-                            numerator += model.gamma_matrix[observation_index][i];
-                        }
-                        denominator += model.gamma_matrix[observation_index][i];
-                    }
-
-                    model.observation_probability_matrix[i][j] = numerator / denominator;
-                }
-            }
+    };
+    let filename = &args[2];
 
-            log_probability = -(model.scale_factors.iter().map(|&scalar| scalar.log2())).sum::<f64>();
-            println!("Finished iteration {:?}. New log probability: {:.*}", iterations, 2, log_probability);
-            iterations += 1;
-        }
+    let path = Path::new(filename);
 
-        println!("Done training.");
+    let raw_input = fs::read_to_string(path)?;
 
-        return model;
+    // Brown corpus paragraphs start on an indented line
+    let paragraphs: Vec<&str> = if split_into_paragraphs {
+        Regex::new("\n\\s+").unwrap().split(&raw_input).collect()
+    } else {
+        vec![raw_input.as_str()]
+    };
+    let sequences: Vec<Box<[usize]>> = paragraphs
+        .iter()
+        // a, b, c, ..., z, SPACE => 0, 1, 2, ..., 25, 26
+        .map(|paragraph| text_to_observations(paragraph, symbols))
+        .filter(|observations| !observations.is_empty())
+        .collect();
+
+    println!("observations length: {:?}", sequences.iter().map(|observations| observations.len()).sum::<usize>());
+    if split_into_paragraphs {
+        println!("paragraphs: {:?}", sequences.len());
     }
 
-    pub fn get_number_of_hidden_state_symbols(&self) -> usize {
-        return self.initial_state_distribution_vector.len();
-    }
+    let time_before_training = SystemTime::now();
+    let model = if min_number_of_hidden_state_symbols == max_number_of_hidden_state_symbols && number_of_restarts == 1 {
+        HmmModel::train_model_on_sequences(min_number_of_hidden_state_symbols, number_of_observation_symbols, &sequences, &training_config)?
+    } else {
+        let model_selection = HmmModel::select_number_of_hidden_states(
+            min_number_of_hidden_state_symbols..=max_number_of_hidden_state_symbols,
+            number_of_restarts,
+            &training_config,
+            criterion,
+            |number_of_hidden_state_symbols, restart_training_config| {
+                HmmModel::train_model_on_sequences(number_of_hidden_state_symbols, number_of_observation_symbols, &sequences, restart_training_config)
+            },
+        )?;
+        print!("{}", model_selection);
+        println!("Selected hidden states: {:?}", model_selection.best_model.get_number_of_hidden_state_symbols());
+        model_selection.best_model
+    };
+    println!("Total training time: {:.*}s", 3, time_before_training.elapsed().unwrap().as_millis() as f64 / 1000.0);
+    let number_of_hidden_state_symbols = model.get_number_of_hidden_state_symbols();
+    println!("Seed: {:?}", model.seed.unwrap());
 
-    pub fn get_number_of_observation_symbols(&self) -> usize {
-        return match self.observation_probability_matrix.len() {
-            0 => 0,
-            _ => self.observation_probability_matrix[0].len(),
-        };
+    if let Some(model_filename) = args.get(3) {
+        model.save(Path::new(model_filename), symbols)?;
+        println!("Saved model to {}", model_filename);
     }
 
-    fn populate_alpha_matrix_and_scale_factors(&mut self, observations: &Box<[usize]>) {
-        self.scale_factors[0] = 0.0;
-        for i in 0..self.get_number_of_hidden_state_symbols() {
-            // alpha_0(i) = pi_i * b_i(O_0)
-            self.alpha_matrix[0][i] = self.initial_state_distribution_vector[i] * self.observation_probability_matrix[i][observations[0]];
-            self.scale_factors[0] += self.alpha_matrix[0][i]
+    for j in 0..number_of_observation_symbols {
+        match j {
+            26 => print!("SPACE    "),
+            _ => print!("{:?}      ", (j as u8 + b'a') as char),
         }
 
-        self.scale_factors[0] = 1.0 / self.scale_factors[0];
-        self.alpha_matrix[0] = self.alpha_matrix[0].iter().map(|&alpha_value| self.scale_factors[0] * alpha_value).collect();
-
-        for observation_index in 1..observations.len() {
-            self.scale_factors[observation_index] = 0.0;
-            for i in 0..self.get_number_of_hidden_state_symbols() {
-                // += alpha_t-1(j) * a_ji
-                self.alpha_matrix[observation_index][i] = self.alpha_matrix[observation_index - 1]
-                    .iter()
-                    .enumerate()
-                    .map(|alpha_value_pair| alpha_value_pair.1 * self.state_transition_matrix[alpha_value_pair.0][i])
-                    .sum::<f64>();
-
-                // = sum(alpha_t-1(j) * a_ji) * b_i(O_t)
-                self.alpha_matrix[observation_index][i] *= self.observation_probability_matrix[i][observations[observation_index]];
-                self.scale_factors[observation_index] += self.alpha_matrix[observation_index][i];
-            }
+        let mut state_max_probability = (0, 0.0);
+        for i in 0..number_of_hidden_state_symbols {
+            print!("{:.*}   ", 5, model.observation_probability_matrix[i][j]);
 
-            self.scale_factors[observation_index] = 1.0 / self.scale_factors[observation_index];
-            for i in 0..self.get_number_of_hidden_state_symbols() {
-                self.alpha_matrix[observation_index][i] *= self.scale_factors[observation_index];
+            if model.observation_probability_matrix[i][j] > state_max_probability.1 {
+                state_max_probability = (i, model.observation_probability_matrix[i][j]);
             }
         }
+        println!("{:?}", state_max_probability.0);
     }
 
-    fn populate_beta_matrix(&mut self, observations: &Box<[usize]>) {
-        // beta_T-1(i) = c_T-1
-        self.beta_matrix[observations.len() - 1] = vec![
-            self.scale_factors[observations.len() - 1];
-            self.get_number_of_hidden_state_symbols()
-        ].into_boxed_slice();
-
-        // From T-2 to 0
-        for observation_index in (0..(observations.len() - 1)).rev() {
-            for i in 0..self.get_number_of_hidden_state_symbols() {
-                self.beta_matrix[observation_index][i] = 0.0;
-                for j in 0..self.get_number_of_hidden_state_symbols() {
-                    // += a_ij * b_j(O_t+1) * beta_t+1(j)
-                    self.beta_matrix[observation_index][i] += self.state_transition_matrix[i][j] * self.observation_probability_matrix[j][observations[observation_index + 1]] * self.beta_matrix[observation_index + 1][j];
-                }
-
-                self.beta_matrix[observation_index][i] *= self.scale_factors[observation_index];
+    // How confidently each character is assigned to each hidden state, averaged over its occurrences
+    let mut posterior_sums = vec![vec![0.0; number_of_hidden_state_symbols]; number_of_observation_symbols];
+    let mut occurrences = vec![0; number_of_observation_symbols];
+    for observations in sequences.iter() {
+        let gamma_matrix = model.posterior_state_probabilities(observations)?;
+        for (gamma_vector, &observation) in gamma_matrix.rows().zip(observations.iter()) {
+            for (posterior_sum, &probability) in posterior_sums[observation].iter_mut().zip(gamma_vector.iter()) {
+                *posterior_sum += probability;
             }
+            occurrences[observation] += 1;
         }
     }
 
-    fn compute_gamma_matrix_and_di_gamma_tensor(&mut self, observations: &Box<[usize]>) {
-        // From 0 to T-2
-        for observation_index in 0..(observations.len() - 1) {
-            let mut denominator = 0.0;
-            for i in 0..self.get_number_of_hidden_state_symbols() {
-                for j in 0..self.get_number_of_hidden_state_symbols() {
-                    // += alpha_t(t) * a_ij * b_j(O_t+1) * beta_t+1(j)
-                    denominator += self.alpha_matrix[observation_index][i] * self.state_transition_matrix[i][j] * self.observation_probability_matrix[j][observations[observation_index + 1]] * self.beta_matrix[observation_index + 1][j];
-                }
-            }
-
-            for i in 0..self.get_number_of_hidden_state_symbols() {
-                self.gamma_matrix[observation_index][i] = 0.0;
-                for j in 0..self.get_number_of_hidden_state_symbols() {
-                    // += (alpha_t(i) * a_ij * b_j(O_t+1) * beta_t+1(j)) / denom
-                    self.di_gamma_tensor[observation_index][i][j] = (self.alpha_matrix[observation_index][i] * self.state_transition_matrix[i][j] * self.observation_probability_matrix[j][observations[observation_index + 1]] * self.beta_matrix[observation_index + 1][j]) / denominator;
-                    // += di-gamma_t(i, j)
-                    self.gamma_matrix[observation_index][i] += self.di_gamma_tensor[observation_index][i][j];
-                }
-            }
+    println!("Average posterior state probabilities:");
+    for j in 0..number_of_observation_symbols {
+        match j {
+            26 => print!("SPACE    "),
+            _ => print!("{:?}      ", (j as u8 + b'a') as char),
         }
 
-        let denominator = self.alpha_matrix[observations.len() - 1].iter().sum::<f64>();
-        self.gamma_matrix[observations.len() - 1] = self.alpha_matrix[observations.len() - 1].iter().map(|&alpha_value| alpha_value / denominator).collect();
+        for posterior_sum in posterior_sums[j].iter() {
+            print!("{:.*}   ", 5, posterior_sum / occurrences[j].max(1) as f64);
+        }
+        println!();
     }
-}
-
-fn main() {
-    let number_of_observation_symbols = 27;
 
-    let args: Box<[String]> = env::args().collect();
-    if args.len() != 3 {
-        print_usage_and_panic();
+    // English-like babble drawn from the trained letter model
+    let symbol_characters: Box<[char]> = symbols.chars().collect();
+    let sampled_sequence = model.sample(200, model.seed)?;
+    println!("Sampled text: {}", sampled_sequence.observations.iter().map(|&observation| symbol_characters[observation]).collect::<String>());
+
+    // Label each character with its most likely hidden state, by Viterbi and then by posterior decoding
+    for observations in sequences.iter() {
+        let viterbi_path = model.viterbi(observations)?;
+        let posterior_path = model.posterior_decode(observations)?;
+        println!("Viterbi log probability: {:.*}", 2, viterbi_path.log_probability);
+        for (line_observations, (line_states, line_posterior_states)) in observations.chunks(80).zip(viterbi_path.state_path.chunks(80).zip(posterior_path.chunks(80))) {
+            println!("{}", line_observations.iter().map(|&observation| symbol_characters[observation]).collect::<String>());
+            println!("{}", line_states.iter().map(|&state| std::char::from_digit(state as u32, 36).unwrap_or('?')).collect::<String>());
+            println!("{}", line_posterior_states.iter().map(|&state| std::char::from_digit(state as u32, 36).unwrap_or('?')).collect::<String>());
+        }
     }
-    let number_of_hidden_state_symbols = args[1].parse::<usize>().unwrap();
-    let filename = &args[2];
 
-    let path = Path::new(filename);
-
-    let mut file = match File::open(&path) {
-        Err(reason) => panic!("Couldn't open file: {:?}", reason),
-        Ok(file) => file,
-    };
-
-    let mut raw_input = String::new();
-    file.read_to_string(&mut raw_input).unwrap();
-
-    let lowercase_input = raw_input.to_lowercase();
-    let no_extra_spaces_no_new_lines = Regex::new("(\n\\s*)|(\\s+\\s+)").unwrap().replace_all(lowercase_input.as_str(), " ").to_string();
-    let sanitized_input = Regex::new("[^a-z ]").unwrap().replace_all(no_extra_spaces_no_new_lines.as_str(), "");
-
-    // a, b, c, ..., z, SPACE => 0, 1, 2, ..., 25, 26
-    let observations: Box<[usize]> = sanitized_input.chars().map(|ch| match ch {
-        ' ' => 26 as usize,
-        _ => ch as usize - 'a' as usize,
-    }).collect();
-
-    println!("observations length: {:?}", observations.len());
+    Ok(())
+}
 
-    let time_before_training = SystemTime::now();
-    let model = HmmModel::train_model(number_of_hidden_state_symbols, number_of_observation_symbols, &observations);
-    println!("Total training time: {:.*}s", 3, time_before_training.elapsed().unwrap().as_millis() as f64 / 1000.0);
+/// Prints a line as each restart finishes instead of after every iteration.
+#[derive(Debug)]
+struct RestartPrinter;
 
-    for j in 0..number_of_observation_symbols {
-        match j {
-            26 => print!("SPACE    "),
-            _ => print!("{:?}      ", (j as u8 + 'a' as u8) as char),
+impl TrainingObserver for RestartPrinter {
+    fn restart_finished(&self, restart: usize, log_probability: Result<f64, &HmmError>) {
+        match log_probability {
+            Ok(log_probability) => println!("Finished restart {:?}. Log probability: {:.*}", restart, 2, log_probability),
+            Err(reason) => println!("Restart {:?} failed: {}", restart, reason),
         }
+    }
+}
 
-        let mut state_max_probability = (0, 0.0);
-        for i in 0..number_of_hidden_state_symbols {
-            print!("{:.*}   ", 5, model.observation_probability_matrix[i][j]);
-
-            if model.observation_probability_matrix[i][j] > state_max_probability.1 {
-                state_max_probability = (i, model.observation_probability_matrix[i][j]);
+/// Pulls `--restarts <count>` and `--aic` out of `args`, leaving the training flags and positional arguments.
+fn take_model_selection_flags(args: &[String]) -> Result<(usize, InformationCriterion, Box<[String]>), HmmError> {
+    let mut number_of_restarts = 1;
+    let mut criterion = InformationCriterion::Bic;
+    let mut remaining_args = Vec::new();
+
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--restarts" => {
+                let value = args_iter.next().ok_or_else(|| HmmError::Parse("Missing value for --restarts".to_string()))?;
+                number_of_restarts = parse_positional_arg("--restarts", value)?;
             }
+            "--aic" => criterion = InformationCriterion::Aic,
+            _ => remaining_args.push(arg.clone()),
         }
-        println!("{:?}", state_max_probability.0);
     }
+
+    Ok((number_of_restarts, criterion, remaining_args.into_boxed_slice()))
 }
 
-fn print_usage_and_panic() {
-    println!("Usage: cargo run <number_of_hidden_state_symbols> <input_file>");
-    panic!("Incorrect command arguments");
+fn print_usage(error: HmmError) -> HmmError {
+    println!("Usage: cargo run [training flags] [--paragraphs] [--restarts <count>] [--aic] <number_of_hidden_state_symbols | min-max> <input_file> [output_model_file]");
+    println!("    --paragraphs                      train on each paragraph as a separate sequence");
+    println!("    --restarts <count>                train this many restarts for each number of hidden states and keep the best, printing one line per restart");
+    println!("    --aic                             given a range of hidden states, pick the one with the lowest AIC instead of BIC");
+    println!("{}", TRAINING_FLAGS_USAGE);
+    error
 }
\ No newline at end of file
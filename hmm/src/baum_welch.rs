@@ -0,0 +1,230 @@
+use rand::rngs::StdRng;
+use rand::Rng;
+
+use crate::{HmmError, Matrix, TrainingConfig, TrainingControl};
+
+/// What `run_baum_welch` hands back for the trainer to copy into its model.
+#[derive(Debug)]
+pub(crate) struct BaumWelchRun<M> {
+    /// The parameters after the final M-step, or with validation, the ones that scored best on it.
+    pub(crate) model: M,
+    pub(crate) iterations: usize,
+    /// log2 P(O | model) from the final E-step, or with validation, under the returned parameters.
+    pub(crate) log_probability: f64,
+    pub(crate) validation_log_probability: Option<f64>,
+}
+
+/// Iterates Baum-Welch on `model` until the log probability converges, `config`'s observer stops it, or the
+/// validation score stops improving. Shared by every kind of model, which only supplies the three steps.
+///
+/// `expectation_step` returns the expected counts under the current parameters and log2 P(O | model).
+/// `score_validation` returns the validation log probability of the current parameters, or `None` without a
+/// validation set. `maximization_step` re-estimates the parameters from the counts, given the iteration.
+pub(crate) fn run_baum_welch<M: Clone, C>(
+    mut model: M,
+    config: &TrainingConfig,
+    mut expectation_step: impl FnMut(&M) -> (C, f64),
+    mut score_validation: impl FnMut(&M) -> Result<Option<f64>, HmmError>,
+    mut maximization_step: impl FnMut(&mut M, &C, usize) -> Result<(), HmmError>,
+) -> Result<BaumWelchRun<M>, HmmError> {
+    let mut iterations = 0;
+    let mut log_probability = f64::NEG_INFINITY;
+    let mut old_log_probability = f64::NEG_INFINITY;
+    let mut best_run: Option<BaumWelchRun<M>> = None;
+    let mut best_validation_log_probability = f64::NEG_INFINITY;
+    let mut iterations_without_validation_improvement = 0;
+
+    while iterations < config.min_iterations || ((log_probability - old_log_probability).abs() > config.improvement_threshold && iterations < config.max_iterations) {
+        old_log_probability = log_probability;
+
+        // Expected counts summed over every sequence
+        let (expected_counts, expected_log_probability) = expectation_step(&model);
+        log_probability = expected_log_probability;
+        if log_probability.is_nan() {
+            return Err(HmmError::NumericalBreakdown(format!("log probability is NaN in iteration {}; log-space forward-backward may avoid this", iterations)));
+        }
+
+        // Iteration 0 runs on the random guess, so only parameters from an M-step are candidates
+        if iterations > 0 {
+            // Scored under the parameters the E-step just used, so both log probabilities describe the same model
+            if let Some(validation_log_probability) = score_validation(&model)? {
+                config.observer.validation_scored(iterations, validation_log_probability);
+
+                if best_run.is_none() || validation_log_probability > best_validation_log_probability {
+                    best_validation_log_probability = validation_log_probability;
+                    best_run = Some(BaumWelchRun {
+                        model: model.clone(),
+                        iterations,
+                        log_probability,
+                        validation_log_probability: Some(validation_log_probability),
+                    });
+                    iterations_without_validation_improvement = 0;
+                } else {
+                    iterations_without_validation_improvement += 1;
+                }
+            }
+        }
+
+        maximization_step(&mut model, &expected_counts, iterations)?;
+
+        let control = config.observer.iteration_finished(iterations, log_probability);
+        iterations += 1;
+        let validation_stopped = iterations >= config.min_iterations && iterations_without_validation_improvement >= config.validation_patience;
+        if control == TrainingControl::Stop || validation_stopped {
+            break;
+        }
+    }
+
+    let converged = (log_probability - old_log_probability).abs() <= config.improvement_threshold;
+    config.observer.training_finished(iterations, log_probability, converged);
+
+    Ok(match best_run {
+        Some(best_run) => BaumWelchRun { iterations, ..best_run },
+        None => BaumWelchRun {
+            model,
+            iterations,
+            log_probability,
+            validation_log_probability: None,
+        },
+    })
+}
+
+/// Fills `values` with random initial guesses from `config`'s range, to be normalized into probabilities.
+pub(crate) fn draw_initial_values(values: &mut [f64], rng: &mut StdRng, config: &TrainingConfig) {
+    for value in values.iter_mut() {
+        *value = rng.gen_range(config.min_initial_value, config.max_initial_value);
+    }
+}
+
+/// Divides `values` by their sum.
+pub(crate) fn normalize(values: &mut [f64]) {
+    let sum = values.iter().sum::<f64>();
+    for value in values.iter_mut() {
+        *value /= sum;
+    }
+}
+
+/// pi_i = (expected times in state i at t = 0 + pseudocount) / (number of possible sequences + N * pseudocount)
+///
+/// A sequence with probability 0 has an all-zero gamma in log space, so it adds nothing to the counts and mustn't
+/// add to the denominator either, or pi would sum to less than 1. Fails if no sequence is possible.
+pub(crate) fn re_estimate_initial_state_distribution_vector(
+    initial_state_distribution_vector: &mut [f64],
+    initial_state_counts: &[f64],
+    number_of_possible_sequences: usize,
+    pseudocount: f64,
+) -> Result<(), HmmError> {
+    let denominator = number_of_possible_sequences as f64 + initial_state_distribution_vector.len() as f64 * pseudocount;
+    if denominator <= 0.0 {
+        return Err(HmmError::ImpossibleObservations);
+    }
+    for (probability, &initial_state_count) in initial_state_distribution_vector.iter_mut().zip(initial_state_counts.iter()) {
+        *probability = (initial_state_count + pseudocount) / denominator;
+    }
+
+    Ok(())
+}
+
+/// Row i = (expected counts of row i + pseudocount) / (expected total of row i + columns * pseudocount), as A and
+/// B are re-estimated. Fails if a total vanishes; `counted` names what the totals count, for the error.
+pub(crate) fn re_estimate_rows(matrix: &mut Matrix, counts: &Matrix, totals: &[f64], pseudocount: f64, counted: &str, iteration: usize) -> Result<(), HmmError> {
+    let number_of_columns = matrix.get_number_of_columns();
+    for (i, (row, counts_row)) in matrix.rows_mut().zip(counts.rows()).enumerate() {
+        let denominator = totals[i] + number_of_columns as f64 * pseudocount;
+        check_denominator(denominator, counted, i, iteration)?;
+        for (probability, &count) in row.iter_mut().zip(counts_row.iter()) {
+            *probability = (count + pseudocount) / denominator;
+        }
+    }
+
+    Ok(())
+}
+
+/// A row of A or B divides by the expected number of times its state is left or emits, which is 0 (or NaN) when
+/// the state is never visited.
+pub(crate) fn check_denominator(denominator: f64, counted: &str, state: usize, iteration: usize) -> Result<(), HmmError> {
+    if denominator > 0.0 {
+        return Ok(());
+    }
+
+    Err(HmmError::NumericalBreakdown(format!(
+        "expected number of {} state {} is {} in iteration {}; a pseudocount keeps it above 0",
+        counted, state, denominator, iteration
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{assert_close, matrix, pseudo_random_observations};
+    use crate::HmmModel;
+
+    #[test]
+    fn a_pseudocount_smooths_the_re_estimated_rows() {
+        let mut parameters = matrix(&[&[0.0, 0.0, 0.0], &[0.0, 0.0, 0.0]]);
+        let counts = matrix(&[&[2.0, 0.0, 1.0], &[0.0, 0.0, 0.0]]);
+
+        re_estimate_rows(&mut parameters, &counts, &[3.0, 0.0], 1.0, "emissions from", 0).unwrap();
+
+        assert_close(&parameters[0], &[0.5, 1.0 / 6.0, 2.0 / 6.0], 1e-12);
+        // A state with no expected visits gets a uniform row instead of failing
+        assert_close(&parameters[1], &[1.0 / 3.0; 3], 1e-12);
+        assert!(matches!(re_estimate_rows(&mut parameters, &counts, &[3.0, 0.0], 0.0, "emissions from", 0), Err(HmmError::NumericalBreakdown(_))));
+    }
+
+    #[test]
+    fn a_pseudocount_keeps_an_unseen_symbol_possible() {
+        // Symbol 2 never occurs in training, so without a pseudocount B gives it probability 0
+        let observations = pseudo_random_observations(100, 2, 1);
+        let config = TrainingConfig::default().iterations(10).seed(2).print_progress(false);
+
+        let unsmoothed_model = HmmModel::train_model(2, 3, &observations, &config).unwrap();
+        let smoothed_model = HmmModel::train_model(2, 3, &observations, &config.observation_pseudocount(0.5)).unwrap();
+
+        assert!(unsmoothed_model.observation_probability_matrix.rows().all(|row| row[2] == 0.0));
+        assert!(smoothed_model.observation_probability_matrix.rows().all(|row| row[2] > 0.0));
+        assert!(smoothed_model.evaluate(&[2]).unwrap().log_probability > f64::NEG_INFINITY);
+    }
+
+    /// Runs Baum-Welch on a counter of M-steps, whose validation score peaks after 3 of them.
+    fn run_with_validation_peak(config: &TrainingConfig) -> BaumWelchRun<usize> {
+        run_baum_welch(
+            0,
+            config,
+            |&m_steps| ((), m_steps as f64),
+            |&m_steps| Ok(Some(-((m_steps as f64 - 3.0).powi(2)))),
+            |m_steps, _, _| {
+                *m_steps += 1;
+                Ok(())
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn training_stops_once_validation_stops_improving() {
+        let config = TrainingConfig::default().min_iterations(1).max_iterations(100).validation_patience(2).print_progress(false);
+
+        let run = run_with_validation_peak(&config);
+
+        // Iterations 4 and 5 score worse than iteration 3, whose parameters are returned
+        assert_eq!(run.iterations, 6);
+        assert_eq!(run.model, 3);
+        assert_eq!(run.log_probability, 3.0);
+        assert_eq!(run.validation_log_probability, Some(0.0));
+
+        let run = run_with_validation_peak(&config.min_iterations(10));
+        assert_eq!((run.iterations, run.model), (10, 3));
+    }
+
+    #[test]
+    fn a_trained_model_keeps_its_best_validation_parameters() {
+        let observations = pseudo_random_observations(60, 3, 1);
+        let validation_sequence = pseudo_random_observations(60, 3, 2);
+        let config = TrainingConfig::default().min_iterations(1).max_iterations(200).improvement_threshold(0.0).seed(3).print_progress(false).validation_sequence(&validation_sequence);
+
+        let model = HmmModel::train_model(2, 3, &observations, &config).unwrap();
+
+        assert_eq!(model.validation_log_probability, Some(model.evaluate(&validation_sequence).unwrap().log_probability));
+    }
+}
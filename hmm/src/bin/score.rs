@@ -0,0 +1,55 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use hmm::{text_to_observations, HmmError, HmmModel, SequenceScore};
+
+/// Scores text against a model saved by one of the chapter 2 experiments.
+///
+/// An input file that can't be read or scored is reported and skipped, and the exit code is 1 if any was.
+fn main() {
+    let args: Box<[String]> = env::args().collect();
+    if args.len() < 3 {
+        print_usage();
+        process::exit(1);
+    }
+
+    let saved_model = match HmmModel::load(Path::new(&args[1])) {
+        Err(reason) => {
+            println!("Couldn't load model: {}", reason);
+            process::exit(1);
+        }
+        Ok(saved_model) => saved_model,
+    };
+
+    let mut number_of_failed_files = 0;
+    for filename in args[2..].iter() {
+        match score_file(&saved_model.model, &saved_model.symbols, filename) {
+            Err(reason) => {
+                println!("{}: {}", filename, reason);
+                number_of_failed_files += 1;
+            }
+            Ok((number_of_observations, score)) => println!(
+                "{}: observations length: {:?}, log probability: {:.*}, per symbol: {:.*}",
+                filename, number_of_observations, 2, score.log_probability, 5, score.log_probability_per_symbol
+            ),
+        }
+    }
+
+    if number_of_failed_files > 0 {
+        process::exit(1);
+    }
+}
+
+fn score_file(model: &HmmModel, symbols: &str, filename: &str) -> Result<(usize, SequenceScore), HmmError> {
+    let raw_input = fs::read_to_string(filename)?;
+    // The same preprocessing the experiments train on, mapped to the model's alphabet
+    let observations = text_to_observations(&raw_input, symbols);
+
+    Ok((observations.len(), model.evaluate(&observations)?))
+}
+
+fn print_usage() {
+    println!("Usage: cargo run --bin score <model_file> <input_file>...");
+}
@@ -0,0 +1,74 @@
+use std::str::FromStr;
+
+use crate::{ForwardBackwardArithmetic, HmmError, TrainingConfig};
+
+/// Describes the flags understood by `parse_training_args`, for the experiments' usage messages.
+pub const TRAINING_FLAGS_USAGE: &str = "Training flags:
+    --min-initial-value <value>       lower bound of the random initial guesses
+    --max-initial-value <value>       upper bound of the random initial guesses
+    --min-iterations <count>          always run at least this many iterations
+    --max-iterations <count>          never run more than this many iterations
+    --iterations <count>              run exactly this many iterations
+    --improvement-threshold <value>   stop once the log probability improves by less than this
+    --seed <value>                    seed for the random initial guesses, to replay a run
+    --pseudocount <value>             add this to every expected count of A, B and pi (default 0)
+    --log-space                       run forward-backward in log space instead of with scale factors
+    --exclude-final-emission          leave each sequence's last observation out of the B update, like older versions
+    --quiet                           don't print progress after every iteration";
+
+/// Applies the training flags in `args` on top of `config`.
+///
+/// Returns the updated config and the remaining positional arguments (including the program name).
+pub fn parse_training_args(args: &[String], config: TrainingConfig) -> Result<(TrainingConfig, Vec<String>), HmmError> {
+    let mut config = config;
+    let mut positional_args = Vec::new();
+    let (mut min_initial_value, mut max_initial_value) = config.get_initial_value_range();
+
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        if !arg.starts_with("--") {
+            positional_args.push(arg.clone());
+            continue;
+        }
+
+        match arg.as_str() {
+            "--min-initial-value" => min_initial_value = parse_flag_value(arg, args_iter.next())?,
+            "--max-initial-value" => max_initial_value = parse_flag_value(arg, args_iter.next())?,
+            "--min-iterations" => config = config.min_iterations(parse_flag_value(arg, args_iter.next())?),
+            "--max-iterations" => config = config.max_iterations(parse_flag_value(arg, args_iter.next())?),
+            "--iterations" => config = config.iterations(parse_flag_value(arg, args_iter.next())?),
+            "--improvement-threshold" => config = config.improvement_threshold(parse_flag_value(arg, args_iter.next())?),
+            "--seed" => config = config.seed(parse_flag_value(arg, args_iter.next())?),
+            "--pseudocount" => {
+                let pseudocount: f64 = parse_flag_value(arg, args_iter.next())?;
+                if pseudocount < 0.0 || pseudocount.is_nan() {
+                    return Err(HmmError::Parse(format!("--pseudocount ({}) must not be negative", pseudocount)));
+                }
+                config = config.pseudocount(pseudocount);
+            }
+            "--log-space" => config = config.forward_backward_arithmetic(ForwardBackwardArithmetic::LogSpace),
+            "--exclude-final-emission" => config = config.exclude_final_emission(true),
+            "--quiet" => config = config.print_progress(false),
+            _ => return Err(HmmError::Parse(format!("Unknown flag: {}", arg))),
+        }
+    }
+
+    if min_initial_value >= max_initial_value {
+        return Err(HmmError::Parse(format!("--min-initial-value ({}) must be less than --max-initial-value ({})", min_initial_value, max_initial_value)));
+    }
+    config = config.initial_value_range(min_initial_value, max_initial_value);
+
+    Ok((config, positional_args))
+}
+
+/// Parses the positional argument `name` of an experiment, e.g. its number of hidden states.
+pub fn parse_positional_arg<T: FromStr>(name: &str, value: &str) -> Result<T, HmmError> {
+    value.parse::<T>().map_err(|_| HmmError::Parse(format!("Invalid value for {}: {:?}", name, value)))
+}
+
+fn parse_flag_value<T: FromStr>(flag: &str, value: Option<&String>) -> Result<T, HmmError> {
+    match value {
+        None => Err(HmmError::Parse(format!("Missing value for {}", flag))),
+        Some(value) => parse_positional_arg(flag, value),
+    }
+}
@@ -0,0 +1,82 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::MODEL_FILE_FORMAT_VERSION;
+
+/// Everything that can go wrong reading input, loading or saving a model, or training one.
+#[derive(Debug)]
+pub enum HmmError {
+    Io(std::io::Error),
+    /// A model file that isn't valid JSON or is missing fields.
+    Json(serde_json::Error),
+    /// A command line argument that couldn't be understood.
+    Parse(String),
+    UnsupportedFormatVersion(u32),
+    ShapeMismatch(String),
+    NotRowStochastic(String),
+    /// An observation that isn't one of the model's M symbols.
+    SymbolOutOfRange { symbol: usize, number_of_observation_symbols: usize },
+    /// Training was given no observations at all.
+    EmptySequence,
+    /// Observations the model gives probability 0, so there's no posterior to decode.
+    ImpossibleObservations,
+    /// An experiment's input is shorter than it needs.
+    TooFewObservations { number_of_observations: usize, required: usize },
+    /// Settings that can't be trained with, e.g. a Gaussian model with a frozen B.
+    InvalidConfiguration(String),
+    /// Training produced NaN or divided by a vanishing expected count.
+    NumericalBreakdown(String),
+}
+
+impl fmt::Display for HmmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HmmError::Io(error) => write!(f, "I/O error: {}", error),
+            HmmError::Json(error) => write!(f, "malformed model file: {}", error),
+            HmmError::Parse(message) => write!(f, "{}", message),
+            HmmError::UnsupportedFormatVersion(version) => write!(f, "unsupported model file format version {} (expected {})", version, MODEL_FILE_FORMAT_VERSION),
+            HmmError::ShapeMismatch(message) => write!(f, "shape mismatch: {}", message),
+            HmmError::NotRowStochastic(message) => write!(f, "not row stochastic: {}", message),
+            HmmError::SymbolOutOfRange { symbol, number_of_observation_symbols } => {
+                write!(f, "observation symbol {} is out of range for a model with {} symbols", symbol, number_of_observation_symbols)
+            }
+            HmmError::EmptySequence => write!(f, "there are no observations to train on"),
+            HmmError::ImpossibleObservations => write!(f, "the observations have probability 0 under the model"),
+            HmmError::TooFewObservations { number_of_observations, required } => {
+                write!(f, "the input has {} observations, at least {} are needed", number_of_observations, required)
+            }
+            HmmError::InvalidConfiguration(message) => write!(f, "invalid configuration: {}", message),
+            HmmError::NumericalBreakdown(message) => write!(f, "numerical breakdown: {}", message),
+        }
+    }
+}
+
+impl Error for HmmError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HmmError::Io(error) => Some(error),
+            HmmError::Json(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for HmmError {
+    fn from(error: std::io::Error) -> HmmError {
+        HmmError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for HmmError {
+    fn from(error: serde_json::Error) -> HmmError {
+        HmmError::Json(error)
+    }
+}
+
+/// Checks that every observation indexes one of `number_of_observation_symbols` symbols.
+pub(crate) fn check_symbols(observations: &[usize], number_of_observation_symbols: usize) -> Result<(), HmmError> {
+    match observations.iter().find(|&&observation| observation >= number_of_observation_symbols) {
+        Some(&symbol) => Err(HmmError::SymbolOutOfRange { symbol, number_of_observation_symbols }),
+        None => Ok(()),
+    }
+}
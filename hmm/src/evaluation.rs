@@ -0,0 +1,163 @@
+use crate::error::check_symbols;
+use crate::forward_backward::{log2_matrix, log_sum_exp2};
+use crate::{ForwardBackwardArithmetic, HmmError, HmmModel};
+
+/// Likelihood of an observation sequence under fixed model parameters.
+#[derive(Debug, Clone, Copy)]
+pub struct SequenceScore {
+    /// log2 P(O | model).
+    pub log_probability: f64,
+    /// `log_probability` divided by the sequence length, so sequences of different lengths can be compared.
+    pub log_probability_per_symbol: f64,
+}
+
+impl SequenceScore {
+    pub(crate) fn new(log_probability: f64, number_of_observations: usize) -> SequenceScore {
+        SequenceScore {
+            log_probability,
+            log_probability_per_symbol: match number_of_observations {
+                0 => 0.0,
+                number_of_observations => log_probability / number_of_observations as f64,
+            },
+        }
+    }
+}
+
+impl HmmModel {
+    /// Scores `observations` with the scaled forward pass alone.
+    ///
+    /// Doesn't touch the model parameters and only keeps the current and previous alpha rows. If alpha vanishes,
+    /// the sequence is scored again in log space, which gives -inf only if it's impossible.
+    pub fn evaluate(&self, observations: &[usize]) -> Result<SequenceScore, HmmError> {
+        self.evaluate_with_arithmetic(observations, ForwardBackwardArithmetic::ScaleFactors)
+    }
+
+    /// `evaluate` with the forward pass in log space from the start, or with scale factors.
+    pub fn evaluate_with_arithmetic(&self, observations: &[usize], arithmetic: ForwardBackwardArithmetic) -> Result<SequenceScore, HmmError> {
+        self.check_parameter_shapes()?;
+        check_symbols(observations, self.get_number_of_observation_symbols())?;
+
+        let log_probability = match arithmetic {
+            ForwardBackwardArithmetic::ScaleFactors => self.scaled_forward_log_probability(observations).unwrap_or_else(|| self.log_space_forward_log_probability(observations)),
+            ForwardBackwardArithmetic::LogSpace => self.log_space_forward_log_probability(observations),
+        };
+
+        Ok(SequenceScore::new(log_probability, observations.len()))
+    }
+
+    /// log2 P(O | model) by the scaled forward pass, or `None` once every alpha vanishes.
+    fn scaled_forward_log_probability(&self, observations: &[usize]) -> Option<f64> {
+        let number_of_hidden_state_symbols = self.get_number_of_hidden_state_symbols();
+        // Row i of the transpose holds a_ji for every j, so the sum below reads contiguous memory
+        let transposed_state_transition_matrix = self.state_transition_matrix.transpose();
+        let mut alpha_vector = vec![0.0; number_of_hidden_state_symbols].into_boxed_slice();
+        let mut previous_alpha_vector = vec![0.0; number_of_hidden_state_symbols].into_boxed_slice();
+        let mut log_probability = 0.0;
+
+        for (observation_index, &observation) in observations.iter().enumerate() {
+            std::mem::swap(&mut alpha_vector, &mut previous_alpha_vector);
+
+            let mut scale_factor = 0.0;
+            for i in 0..number_of_hidden_state_symbols {
+                alpha_vector[i] = if observation_index == 0 {
+                    // alpha_0(i) = pi_i * b_i(O_0)
+                    self.initial_state_distribution_vector[i] * self.observation_probability_matrix[i][observation]
+                } else {
+                    // alpha_t(i) = sum(alpha_t-1(j) * a_ji) * b_i(O_t)
+                    previous_alpha_vector
+                        .iter()
+                        .zip(transposed_state_transition_matrix[i].iter())
+                        .map(|(alpha_value, state_transition_probability)| alpha_value * state_transition_probability)
+                        .sum::<f64>() * self.observation_probability_matrix[i][observation]
+                };
+                scale_factor += alpha_vector[i];
+            }
+
+            scale_factor = 1.0 / scale_factor;
+            // Underflow or an impossible sequence, and either way every later alpha would be NaN
+            if !scale_factor.is_finite() {
+                return None;
+            }
+            for alpha_value in alpha_vector.iter_mut() {
+                *alpha_value *= scale_factor;
+            }
+
+            // log P(O | model) = -sum(log c_t)
+            log_probability -= scale_factor.log2();
+        }
+
+        Some(log_probability)
+    }
+
+    /// log2 P(O | model) by the forward pass on log2 alpha, which only reaches -inf if the sequence is impossible.
+    fn log_space_forward_log_probability(&self, observations: &[usize]) -> f64 {
+        let number_of_hidden_state_symbols = self.get_number_of_hidden_state_symbols();
+        let transposed_log_state_transition_matrix = log2_matrix(&self.state_transition_matrix.transpose());
+        let mut log_alpha_vector = vec![0.0; number_of_hidden_state_symbols].into_boxed_slice();
+        let mut previous_log_alpha_vector = vec![0.0; number_of_hidden_state_symbols].into_boxed_slice();
+        let mut terms = vec![0.0; number_of_hidden_state_symbols].into_boxed_slice();
+
+        for (observation_index, &observation) in observations.iter().enumerate() {
+            std::mem::swap(&mut log_alpha_vector, &mut previous_log_alpha_vector);
+
+            for i in 0..number_of_hidden_state_symbols {
+                let log_state_probability = if observation_index == 0 {
+                    // log alpha_0(i) = log pi_i + log b_i(O_0)
+                    self.initial_state_distribution_vector[i].log2()
+                } else {
+                    // log alpha_t(i) = log(sum(alpha_t-1(j) * a_ji)) + log b_i(O_t)
+                    for ((term, &previous_log_alpha_value), &log_state_transition_probability) in terms.iter_mut().zip(previous_log_alpha_vector.iter()).zip(transposed_log_state_transition_matrix[i].iter()) {
+                        *term = previous_log_alpha_value + log_state_transition_probability;
+                    }
+                    log_sum_exp2(&terms)
+                };
+                log_alpha_vector[i] = log_state_probability + self.observation_probability_matrix[i][observation].log2();
+            }
+        }
+
+        match observations.len() {
+            0 => 0.0,
+            _ => log_sum_exp2(&log_alpha_vector),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::forward_backward::ForwardBackward;
+    use crate::test_support::{matrix, test_model};
+
+    #[test]
+    fn both_arithmetics_score_alike() {
+        let model = test_model();
+        let observations: Box<[usize]> = (0..2000).map(|t| (t * 7 / 3) % 3).collect();
+
+        let scaled = model.evaluate_with_arithmetic(&observations, ForwardBackwardArithmetic::ScaleFactors).unwrap();
+        let log_space = model.evaluate_with_arithmetic(&observations, ForwardBackwardArithmetic::LogSpace).unwrap();
+
+        assert!((scaled.log_probability - log_space.log_probability).abs() < 1e-9 * scaled.log_probability.abs());
+        assert!((scaled.log_probability_per_symbol - scaled.log_probability / 2000.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn an_impossible_sequence_scores_negative_infinity() {
+        let model = HmmModel::new(matrix(&[&[1.0, 0.0], &[0.0, 1.0]]), matrix(&[&[0.5, 0.5, 0.0], &[0.5, 0.5, 0.0]]), vec![0.5, 0.5].into_boxed_slice());
+
+        for arithmetic in [ForwardBackwardArithmetic::ScaleFactors, ForwardBackwardArithmetic::LogSpace] {
+            assert_eq!(model.evaluate_with_arithmetic(&[0, 2, 1], arithmetic).unwrap().log_probability, f64::NEG_INFINITY);
+        }
+    }
+
+    #[test]
+    fn log_space_matches_the_training_log_probability() {
+        let model = test_model();
+        let observations: Box<[usize]> = (0..300).map(|t| (t * t) % 3).collect();
+        let emission_probability_matrix = model.emission_probability_matrix(&observations).unwrap();
+        let mut forward_backward = ForwardBackward::new(2, observations.len(), ForwardBackwardArithmetic::ScaleFactors);
+        forward_backward.compute(&model.state_transition_matrix, &model.initial_state_distribution_vector, &emission_probability_matrix, None);
+
+        let score = model.evaluate_with_arithmetic(&observations, ForwardBackwardArithmetic::LogSpace).unwrap();
+        assert!((score.log_probability - forward_backward.log_probability()).abs() < 1e-9 * score.log_probability.abs());
+    }
+}
@@ -0,0 +1,322 @@
+use rayon::prelude::*;
+
+use crate::forward_backward::{ForwardBackward, ForwardBackwardArithmetic};
+use crate::{HmmModel, Matrix};
+
+/// Consecutive sequences are grouped until a chunk holds at least this many observations, so each thread gets
+/// enough work to outweigh the cost of summing one more set of counts.
+const MIN_OBSERVATIONS_PER_CHUNK: usize = 1 << 14;
+
+/// Expected counts from the E-step, summed over some of the training sequences.
+#[derive(Debug)]
+pub(crate) struct ExpectedCounts {
+    pub(crate) initial_state_counts: Box<[f64]>,
+    pub(crate) state_transition_counts: Matrix,
+    pub(crate) state_transition_totals: Box<[f64]>,
+    pub(crate) observation_counts: Matrix,
+    pub(crate) observation_totals: Box<[f64]>,
+    /// Sequences with a probability above 0, the only ones gamma counts.
+    pub(crate) number_of_possible_sequences: usize,
+    /// log2 P(O | model) summed over the same sequences.
+    pub(crate) log_probability: f64,
+}
+
+/// Consecutive training sequences of type `S` that one thread runs forward-backward on, with the buffers `F` it
+/// reuses every iteration.
+pub(crate) struct SequenceChunk<'a, S, F> {
+    sequences: &'a [S],
+    forward_backwards: Box<[F]>,
+}
+
+impl ExpectedCounts {
+    fn new(number_of_hidden_state_symbols: usize, number_of_observation_symbols: usize) -> ExpectedCounts {
+        ExpectedCounts {
+            initial_state_counts: vec![0.0; number_of_hidden_state_symbols].into_boxed_slice(),
+            state_transition_counts: Matrix::new(number_of_hidden_state_symbols, number_of_hidden_state_symbols, 0.0),
+            state_transition_totals: vec![0.0; number_of_hidden_state_symbols].into_boxed_slice(),
+            observation_counts: Matrix::new(number_of_hidden_state_symbols, number_of_observation_symbols, 0.0),
+            observation_totals: vec![0.0; number_of_hidden_state_symbols].into_boxed_slice(),
+            number_of_possible_sequences: 0,
+            log_probability: 0.0,
+        }
+    }
+
+    fn add_sequence(&mut self, forward_backward: &mut ForwardBackward, model: &HmmModel, observations: &[usize], exclude_final_emission: bool) {
+        let emission_probability_matrix = model.lookup_emission_probabilities(observations);
+        forward_backward.compute(&model.state_transition_matrix, &model.initial_state_distribution_vector, &emission_probability_matrix, Some(&mut self.state_transition_counts));
+
+        for (initial_state_count, &gamma_value) in self.initial_state_counts.iter_mut().zip(forward_backward.gamma_matrix[0].iter()) {
+            *initial_state_count += gamma_value;
+        }
+
+        // Binning gamma_t(i) by O_t so B takes one pass instead of one per symbol
+        for (observation_index, &observation) in observations.iter().enumerate() {
+            let is_final_observation = observation_index == observations.len() - 1;
+            for (i, &gamma_value) in forward_backward.gamma_matrix[observation_index].iter().enumerate() {
+                // From 0 to T-2, since nothing follows the last observation
+                if !is_final_observation {
+                    self.state_transition_totals[i] += gamma_value;
+                }
+
+                // From 0 to T-1, unless the final emission is left out for compatibility
+                if !is_final_observation || !exclude_final_emission {
+                    self.observation_totals[i] += gamma_value;
+                    self.observation_counts[i][observation] += gamma_value;
+                }
+            }
+        }
+
+        if forward_backward.log_probability() > f64::NEG_INFINITY {
+            self.number_of_possible_sequences += 1;
+        }
+        self.log_probability += forward_backward.log_probability();
+    }
+
+    fn add(&mut self, other: &ExpectedCounts) {
+        add_vector(&mut self.initial_state_counts, &other.initial_state_counts);
+        add_matrix(&mut self.state_transition_counts, &other.state_transition_counts);
+        add_vector(&mut self.state_transition_totals, &other.state_transition_totals);
+        add_matrix(&mut self.observation_counts, &other.observation_counts);
+        add_vector(&mut self.observation_totals, &other.observation_totals);
+        self.number_of_possible_sequences += other.number_of_possible_sequences;
+        self.log_probability += other.log_probability;
+    }
+}
+
+/// Groups `sequences` into chunks of at least `MIN_OBSERVATIONS_PER_CHUNK` observations (the last may be smaller),
+/// giving each sequence the buffers `new_forward_backward` makes for it.
+///
+/// The grouping only depends on the sequence lengths, never on the number of threads, so training gives the
+/// same model on any machine. A single long sequence stays in one chunk: forward-backward can't be split
+/// without changing the result, so split the text into independent sequences to use more than one core.
+pub(crate) fn chunk_sequences<'a, S, F>(sequences: &'a [S], sequence_length: impl Fn(&S) -> usize, new_forward_backward: impl Fn(&S) -> F) -> Vec<SequenceChunk<'a, S, F>> {
+    let mut chunks = Vec::new();
+    let mut chunk_start = 0;
+    let mut chunk_observations = 0;
+
+    for (sequence_index, sequence) in sequences.iter().enumerate() {
+        chunk_observations += sequence_length(sequence);
+        if chunk_observations >= MIN_OBSERVATIONS_PER_CHUNK || sequence_index == sequences.len() - 1 {
+            let sequences_in_chunk = &sequences[chunk_start..=sequence_index];
+            chunks.push(SequenceChunk {
+                sequences: sequences_in_chunk,
+                forward_backwards: sequences_in_chunk.iter().map(&new_forward_backward).collect(),
+            });
+
+            chunk_start = sequence_index + 1;
+            chunk_observations = 0;
+        }
+    }
+
+    chunks
+}
+
+/// `chunk_sequences` for discrete observation sequences.
+pub(crate) fn chunk_symbol_sequences<'a>(
+    sequences: &'a [&'a [usize]],
+    number_of_hidden_state_symbols: usize,
+    arithmetic: ForwardBackwardArithmetic,
+) -> Vec<SequenceChunk<'a, &'a [usize], ForwardBackward>> {
+    chunk_sequences(sequences, |sequence| sequence.len(), |sequence| ForwardBackward::new(number_of_hidden_state_symbols, sequence.len(), arithmetic))
+}
+
+/// Runs `add_sequence` over every chunk in parallel, each chunk into its own counts from `new_counts`, then sums
+/// the chunks' counts in chunk order with `add_counts`.
+///
+/// Summing in a fixed order keeps the result deterministic; it matches a serial pass over the sequences up to
+/// floating-point rounding, and exactly when there's only one chunk.
+pub(crate) fn sum_over_chunks<S: Sync, F: Send, C: Send>(
+    chunks: &mut [SequenceChunk<S, F>],
+    new_counts: impl Fn() -> C + Sync,
+    add_sequence: impl Fn(&mut C, &mut F, &S) + Sync,
+    add_counts: impl Fn(&mut C, &C),
+) -> C {
+    let chunk_counts: Vec<C> = chunks
+        .par_iter_mut()
+        .map(|chunk| {
+            let mut counts = new_counts();
+            for (forward_backward, sequence) in chunk.forward_backwards.iter_mut().zip(chunk.sequences.iter()) {
+                add_sequence(&mut counts, forward_backward, sequence);
+            }
+            counts
+        })
+        .collect();
+
+    let mut chunk_counts = chunk_counts.into_iter();
+    let mut counts = chunk_counts.next().unwrap_or_else(new_counts);
+    for other_counts in chunk_counts {
+        add_counts(&mut counts, &other_counts);
+    }
+
+    counts
+}
+
+/// Runs the E-step on every chunk in parallel and sums the expected counts.
+pub(crate) fn compute_expected_counts(chunks: &mut [SequenceChunk<&[usize], ForwardBackward>], model: &HmmModel, exclude_final_emission: bool) -> ExpectedCounts {
+    let number_of_hidden_state_symbols = model.get_number_of_hidden_state_symbols();
+    let number_of_observation_symbols = model.get_number_of_observation_symbols();
+
+    sum_over_chunks(
+        chunks,
+        || ExpectedCounts::new(number_of_hidden_state_symbols, number_of_observation_symbols),
+        |expected_counts, forward_backward, observations| expected_counts.add_sequence(forward_backward, model, observations, exclude_final_emission),
+        ExpectedCounts::add,
+    )
+}
+
+pub(crate) fn add_vector(vector: &mut [f64], other_vector: &[f64]) {
+    for (value, &other_value) in vector.iter_mut().zip(other_vector.iter()) {
+        *value += other_value;
+    }
+}
+
+pub(crate) fn add_matrix(matrix: &mut Matrix, other_matrix: &Matrix) {
+    for (row, other_row) in matrix.rows_mut().zip(other_matrix.rows()) {
+        add_vector(row, other_row);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{assert_close, assert_matrices_close, every_state_path, matrix, pseudo_random_observations, test_model};
+    use crate::{FrozenParameters, TrainingConfig};
+
+    /// Expected counts by summing P(X, O) over every hidden state sequence X.
+    fn enumerate_expected_counts(model: &HmmModel, observations: &[usize]) -> ExpectedCounts {
+        let n = model.get_number_of_hidden_state_symbols();
+        let mut expected_counts = ExpectedCounts::new(n, model.get_number_of_observation_symbols());
+        let mut total_probability = 0.0;
+
+        for state_path in every_state_path(n, observations.len()) {
+            let mut probability = model.initial_state_distribution_vector[state_path[0]] * model.observation_probability_matrix[state_path[0]][observations[0]];
+            for t in 1..observations.len() {
+                probability *= model.state_transition_matrix[state_path[t - 1]][state_path[t]] * model.observation_probability_matrix[state_path[t]][observations[t]];
+            }
+            total_probability += probability;
+
+            expected_counts.initial_state_counts[state_path[0]] += probability;
+            for t in 0..observations.len() {
+                if t < observations.len() - 1 {
+                    expected_counts.state_transition_counts[state_path[t]][state_path[t + 1]] += probability;
+                    expected_counts.state_transition_totals[state_path[t]] += probability;
+                }
+                expected_counts.observation_counts[state_path[t]][observations[t]] += probability;
+                expected_counts.observation_totals[state_path[t]] += probability;
+            }
+        }
+
+        for value in expected_counts.initial_state_counts.iter_mut().chain(expected_counts.state_transition_totals.iter_mut()).chain(expected_counts.observation_totals.iter_mut()) {
+            *value /= total_probability;
+        }
+        for row in expected_counts.state_transition_counts.rows_mut().chain(expected_counts.observation_counts.rows_mut()) {
+            for value in row.iter_mut() {
+                *value /= total_probability;
+            }
+        }
+        expected_counts.log_probability = total_probability.log2();
+
+        expected_counts
+    }
+
+    fn assert_counts_close(actual: &ExpectedCounts, expected: &ExpectedCounts, tolerance: f64) {
+        assert_close(&actual.initial_state_counts, &expected.initial_state_counts, tolerance);
+        assert_matrices_close(&actual.state_transition_counts, &expected.state_transition_counts, tolerance);
+        assert_close(&actual.state_transition_totals, &expected.state_transition_totals, tolerance);
+        assert_matrices_close(&actual.observation_counts, &expected.observation_counts, tolerance);
+        assert_close(&actual.observation_totals, &expected.observation_totals, tolerance);
+        assert!((actual.log_probability - expected.log_probability).abs() <= tolerance * actual.log_probability.abs().max(1.0));
+    }
+
+    #[test]
+    fn expected_counts_match_enumeration() {
+        let model = test_model();
+        let observations = [2, 0, 1, 1, 2, 0, 0];
+
+        for arithmetic in [ForwardBackwardArithmetic::ScaleFactors, ForwardBackwardArithmetic::LogSpace] {
+            let sequences = [&observations[..]];
+            let mut chunks = chunk_symbol_sequences(&sequences, model.get_number_of_hidden_state_symbols(), arithmetic);
+            let expected_counts = compute_expected_counts(&mut chunks, &model, false);
+
+            assert_counts_close(&expected_counts, &enumerate_expected_counts(&model, &observations), 1e-12);
+        }
+    }
+
+    #[test]
+    fn excluding_the_final_emission_drops_only_its_counts() {
+        let model = test_model();
+        let observations = [1, 2, 2, 0];
+        let sequences = [&observations[..]];
+        let mut chunks = chunk_symbol_sequences(&sequences, model.get_number_of_hidden_state_symbols(), ForwardBackwardArithmetic::ScaleFactors);
+
+        let all_emissions = compute_expected_counts(&mut chunks, &model, false);
+        let without_final_emission = compute_expected_counts(&mut chunks, &model, true);
+
+        // Both now sum gamma over t = 0..T-2
+        assert_close(&without_final_emission.observation_totals, &all_emissions.state_transition_totals, 1e-12);
+        assert_close(&without_final_emission.state_transition_totals, &all_emissions.state_transition_totals, 0.0);
+    }
+
+    #[test]
+    fn training_re_estimates_from_the_expected_counts() {
+        let observations = [2, 0, 1, 1, 2, 0, 0, 1];
+        let config = TrainingConfig::default().print_progress(false).seed(11);
+        let after_one_iteration = HmmModel::train_model(2, 3, &observations, &config.clone().iterations(1)).unwrap();
+        let after_two_iterations = HmmModel::train_model(2, 3, &observations, &config.iterations(2)).unwrap();
+
+        // The second iteration scores and re-estimates the parameters the first one left
+        let expected_counts = enumerate_expected_counts(&after_one_iteration, &observations);
+        assert!((after_two_iterations.log_probability - expected_counts.log_probability).abs() < 1e-12);
+        assert_close(&after_two_iterations.initial_state_distribution_vector, &expected_counts.initial_state_counts, 1e-12);
+        for i in 0..2 {
+            let state_transition_row: Vec<f64> = expected_counts.state_transition_counts[i].iter().map(|count| count / expected_counts.state_transition_totals[i]).collect();
+            assert_close(&after_two_iterations.state_transition_matrix[i], &state_transition_row, 1e-12);
+            let observation_row: Vec<f64> = expected_counts.observation_counts[i].iter().map(|count| count / expected_counts.observation_totals[i]).collect();
+            assert_close(&after_two_iterations.observation_probability_matrix[i], &observation_row, 1e-12);
+        }
+    }
+
+    #[test]
+    fn an_impossible_sequence_leaves_pi_a_distribution() {
+        // Neither state emits symbol 2, so the second sequence has probability 0 and an all-zero gamma in log space
+        let frozen_parameters = FrozenParameters::default().observation_probability_matrix(matrix(&[&[0.5, 0.5, 0.0], &[0.2, 0.8, 0.0]]));
+        let config = TrainingConfig::default().print_progress(false).seed(5).iterations(3).frozen_parameters(frozen_parameters).forward_backward_arithmetic(ForwardBackwardArithmetic::LogSpace);
+        let sequences = [vec![0, 1, 0, 1], vec![0, 2, 1], vec![1, 1, 0]];
+
+        let model = HmmModel::train_model_on_sequences(2, 3, &sequences, &config).unwrap();
+
+        assert!((model.initial_state_distribution_vector.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn chunks_hold_enough_observations_and_cover_every_sequence() {
+        let sequences: Vec<Vec<usize>> = (0..10).map(|length| vec![0; length * 5000 + 1]).collect();
+        let chunks = chunk_sequences(&sequences, |sequence| sequence.len(), |_| ());
+
+        assert_eq!(chunks.iter().map(|chunk| chunk.sequences.len()).sum::<usize>(), sequences.len());
+        for chunk in chunks[..chunks.len() - 1].iter() {
+            assert!(chunk.sequences.iter().map(|sequence| sequence.len()).sum::<usize>() >= MIN_OBSERVATIONS_PER_CHUNK);
+        }
+    }
+
+    #[test]
+    fn chunked_counts_match_a_serial_pass() {
+        let model = test_model();
+        // Enough observations for several chunks
+        let sequences: Vec<Box<[usize]>> = (0..50).map(|seed| pseudo_random_observations(997, 3, seed)).collect();
+        let sequences: Vec<&[usize]> = sequences.iter().map(|sequence| &sequence[..]).collect();
+
+        let mut chunks = chunk_symbol_sequences(&sequences, 2, ForwardBackwardArithmetic::ScaleFactors);
+        assert!(chunks.len() > 1);
+        let chunked_counts = compute_expected_counts(&mut chunks, &model, false);
+
+        let mut serial_counts = ExpectedCounts::new(2, 3);
+        for observations in sequences.iter() {
+            let mut forward_backward = ForwardBackward::new(2, observations.len(), ForwardBackwardArithmetic::ScaleFactors);
+            serial_counts.add_sequence(&mut forward_backward, &model, observations, false);
+        }
+
+        // Only the order of the additions differs
+        assert_counts_close(&chunked_counts, &serial_counts, 1e-9);
+    }
+}
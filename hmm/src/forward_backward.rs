@@ -0,0 +1,333 @@
+use crate::Matrix;
+
+/// How forward-backward keeps alpha and beta from underflowing on long sequences.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ForwardBackwardArithmetic {
+    /// Normalize alpha at every time step and scale beta by the same factors (Rabiner's scaling). The default,
+    /// and the faster of the two.
+    #[default]
+    ScaleFactors,
+    /// Keep log2 alpha and log2 beta and add probabilities with log-sum-exp.
+    ///
+    /// Slower, but still works when every state's alpha underflows to 0 at some time step, where a scale factor
+    /// would be 1 / 0 and turn everything after it into NaN.
+    LogSpace,
+}
+
+/// Alpha, beta and gamma for one observation sequence under fixed model parameters.
+///
+/// Only sees the observations through the emission probability matrix, where row t holds b_i(O_t) for every
+/// state i, so discrete and continuous models share it.
+///
+/// Di-gamma is never stored: each di-gamma_t(i, j) is added to the caller's expected transition counts as
+/// soon as it's computed, so memory stays O(T * N) instead of O(T * N * N).
+///
+/// With `ForwardBackwardArithmetic::LogSpace` the alpha and beta matrices hold log2 alpha and log2 beta, unscaled,
+/// and the scale factors go unused. Gamma is a plain probability either way.
+#[derive(Debug)]
+pub(crate) struct ForwardBackward {
+    pub(crate) alpha_matrix: Matrix,
+    pub(crate) beta_matrix: Matrix,
+    pub(crate) gamma_matrix: Matrix,
+    pub(crate) scale_factors: Box<[f64]>,
+    arithmetic: ForwardBackwardArithmetic,
+    log_probability: f64,
+}
+
+impl ForwardBackward {
+    pub(crate) fn new(number_of_hidden_state_symbols: usize, number_of_observations: usize, arithmetic: ForwardBackwardArithmetic) -> ForwardBackward {
+        ForwardBackward {
+            alpha_matrix: Matrix::new(number_of_observations, number_of_hidden_state_symbols, 0.0),
+            beta_matrix: Matrix::new(number_of_observations, number_of_hidden_state_symbols, 0.0),
+            gamma_matrix: Matrix::new(number_of_observations, number_of_hidden_state_symbols, 0.0),
+            scale_factors: vec![0.0; number_of_observations].into_boxed_slice(),
+            arithmetic,
+            log_probability: f64::NEG_INFINITY,
+        }
+    }
+
+    /// Runs the forward and backward passes and fills in gamma.
+    ///
+    /// If `di_gamma_sums` is given, di-gamma_t(i, j) for t = 0..T-2 is added to `di_gamma_sums[i][j]`.
+    pub(crate) fn compute(&mut self, state_transition_matrix: &Matrix, initial_state_distribution_vector: &[f64], emission_probability_matrix: &Matrix, di_gamma_sums: Option<&mut Matrix>) {
+        if self.arithmetic == ForwardBackwardArithmetic::LogSpace {
+            self.compute_in_log_space(state_transition_matrix, initial_state_distribution_vector, emission_probability_matrix, di_gamma_sums);
+            return;
+        }
+
+        // Row i of the transpose holds a_ji for every j, so the forward pass reads contiguous memory
+        let transposed_state_transition_matrix = state_transition_matrix.transpose();
+
+        self.populate_alpha_matrix_and_scale_factors(&transposed_state_transition_matrix, initial_state_distribution_vector, emission_probability_matrix);
+        self.populate_beta_matrix(state_transition_matrix, emission_probability_matrix);
+        self.compute_gamma_matrix_and_di_gamma_sums(state_transition_matrix, emission_probability_matrix, di_gamma_sums);
+        self.log_probability = -(self.scale_factors.iter().map(|&scalar| scalar.log2())).sum::<f64>();
+    }
+
+    /// log2 P(O | model), available once `compute` has run.
+    pub(crate) fn log_probability(&self) -> f64 {
+        self.log_probability
+    }
+
+    fn populate_alpha_matrix_and_scale_factors(&mut self, transposed_state_transition_matrix: &Matrix, initial_state_distribution_vector: &[f64], emission_probability_matrix: &Matrix) {
+        self.scale_factors[0] = 0.0;
+        for (i, &initial_state_probability) in initial_state_distribution_vector.iter().enumerate() {
+            // alpha_0(i) = pi_i * b_i(O_0)
+            self.alpha_matrix[0][i] = initial_state_probability * emission_probability_matrix[0][i];
+            self.scale_factors[0] += self.alpha_matrix[0][i]
+        }
+
+        self.scale_factors[0] = 1.0 / self.scale_factors[0];
+        let scale_factor = self.scale_factors[0];
+        for alpha_value in self.alpha_matrix[0].iter_mut() {
+            *alpha_value *= scale_factor;
+        }
+
+        for (observation_index, observation_probability_vector) in emission_probability_matrix.rows().enumerate().skip(1) {
+            self.scale_factors[observation_index] = 0.0;
+            for (i, &observation_probability) in observation_probability_vector.iter().enumerate() {
+                // += alpha_t-1(j) * a_ji
+                self.alpha_matrix[observation_index][i] = self.alpha_matrix[observation_index - 1]
+                    .iter()
+                    .zip(transposed_state_transition_matrix[i].iter())
+                    .map(|(alpha_value, state_transition_probability)| alpha_value * state_transition_probability)
+                    .sum::<f64>();
+
+                // = sum(alpha_t-1(j) * a_ji) * b_i(O_t)
+                self.alpha_matrix[observation_index][i] *= observation_probability;
+                self.scale_factors[observation_index] += self.alpha_matrix[observation_index][i];
+            }
+
+            self.scale_factors[observation_index] = 1.0 / self.scale_factors[observation_index];
+            let scale_factor = self.scale_factors[observation_index];
+            for alpha_value in self.alpha_matrix[observation_index].iter_mut() {
+                *alpha_value *= scale_factor;
+            }
+        }
+    }
+
+    fn populate_beta_matrix(&mut self, state_transition_matrix: &Matrix, emission_probability_matrix: &Matrix) {
+        let number_of_observations = emission_probability_matrix.get_number_of_rows();
+
+        // beta_T-1(i) = c_T-1
+        let last_scale_factor = self.scale_factors[number_of_observations - 1];
+        for beta_value in self.beta_matrix[number_of_observations - 1].iter_mut() {
+            *beta_value = last_scale_factor;
+        }
+
+        // From T-2 to 0
+        for observation_index in (0..(number_of_observations - 1)).rev() {
+            let next_observation_probability_vector = &emission_probability_matrix[observation_index + 1];
+
+            for (i, state_transition_vector) in state_transition_matrix.rows().enumerate() {
+                let mut beta_value = 0.0;
+                for ((state_transition_probability, observation_probability), next_beta_value) in state_transition_vector
+                    .iter()
+                    .zip(next_observation_probability_vector.iter())
+                    .zip(self.beta_matrix[observation_index + 1].iter())
+                {
+                    // += a_ij * b_j(O_t+1) * beta_t+1(j)
+                    beta_value += state_transition_probability * observation_probability * next_beta_value;
+                }
+
+                self.beta_matrix[observation_index][i] = beta_value * self.scale_factors[observation_index];
+            }
+        }
+    }
+
+    fn compute_gamma_matrix_and_di_gamma_sums(&mut self, state_transition_matrix: &Matrix, emission_probability_matrix: &Matrix, mut di_gamma_sums: Option<&mut Matrix>) {
+        let number_of_hidden_state_symbols = state_transition_matrix.get_number_of_rows();
+        let number_of_observations = emission_probability_matrix.get_number_of_rows();
+
+        // From 0 to T-2
+        for observation_index in 0..(number_of_observations - 1) {
+            let alpha_vector = &self.alpha_matrix[observation_index];
+            let next_beta_vector = &self.beta_matrix[observation_index + 1];
+            let next_observation_probability_vector = &emission_probability_matrix[observation_index + 1];
+
+            let mut denominator = 0.0;
+            for (alpha_value, state_transition_vector) in alpha_vector.iter().zip(state_transition_matrix.rows()) {
+                for j in 0..number_of_hidden_state_symbols {
+                    // += alpha_t(t) * a_ij * b_j(O_t+1) * beta_t+1(j)
+                    denominator += alpha_value * state_transition_vector[j] * next_observation_probability_vector[j] * next_beta_vector[j];
+                }
+            }
+
+            let gamma_vector = &mut self.gamma_matrix[observation_index];
+            for i in 0..number_of_hidden_state_symbols {
+                let state_transition_vector = &state_transition_matrix[i];
+                gamma_vector[i] = 0.0;
+                for j in 0..number_of_hidden_state_symbols {
+                    // = (alpha_t(i) * a_ij * b_j(O_t+1) * beta_t+1(j)) / denom
+                    let di_gamma = (alpha_vector[i] * state_transition_vector[j] * next_observation_probability_vector[j] * next_beta_vector[j]) / denominator;
+                    // += di-gamma_t(i, j)
+                    gamma_vector[i] += di_gamma;
+                    if let Some(di_gamma_sums) = di_gamma_sums.as_deref_mut() {
+                        di_gamma_sums[i][j] += di_gamma;
+                    }
+                }
+            }
+        }
+
+        let last_alpha_vector = &self.alpha_matrix[number_of_observations - 1];
+        let denominator = last_alpha_vector.iter().sum::<f64>();
+        for (gamma_value, &alpha_value) in self.gamma_matrix[number_of_observations - 1].iter_mut().zip(last_alpha_vector.iter()) {
+            *gamma_value = alpha_value / denominator;
+        }
+    }
+
+    fn compute_in_log_space(&mut self, state_transition_matrix: &Matrix, initial_state_distribution_vector: &[f64], emission_probability_matrix: &Matrix, di_gamma_sums: Option<&mut Matrix>) {
+        let number_of_hidden_state_symbols = state_transition_matrix.get_number_of_rows();
+        let number_of_observations = emission_probability_matrix.get_number_of_rows();
+        let log_state_transition_matrix = log2_matrix(state_transition_matrix);
+        let transposed_log_state_transition_matrix = log_state_transition_matrix.transpose();
+        let log_emission_probability_matrix = log2_matrix(emission_probability_matrix);
+        let mut terms = vec![0.0; number_of_hidden_state_symbols].into_boxed_slice();
+
+        // log alpha_0(i) = log pi_i + log b_i(O_0)
+        for ((alpha_value, &initial_state_probability), &log_observation_probability) in self.alpha_matrix[0].iter_mut().zip(initial_state_distribution_vector.iter()).zip(log_emission_probability_matrix[0].iter()) {
+            *alpha_value = initial_state_probability.log2() + log_observation_probability;
+        }
+        for observation_index in 1..number_of_observations {
+            for i in 0..number_of_hidden_state_symbols {
+                // log alpha_t(i) = log(sum(alpha_t-1(j) * a_ji)) + log b_i(O_t)
+                for ((term, &previous_alpha_value), &log_state_transition_probability) in terms.iter_mut().zip(self.alpha_matrix[observation_index - 1].iter()).zip(transposed_log_state_transition_matrix[i].iter()) {
+                    *term = previous_alpha_value + log_state_transition_probability;
+                }
+                self.alpha_matrix[observation_index][i] = log_sum_exp2(&terms) + log_emission_probability_matrix[observation_index][i];
+            }
+        }
+        self.log_probability = log_sum_exp2(&self.alpha_matrix[number_of_observations - 1]);
+
+        // log beta_T-1(i) = log 1
+        self.beta_matrix[number_of_observations - 1].fill(0.0);
+        for observation_index in (0..(number_of_observations - 1)).rev() {
+            for i in 0..number_of_hidden_state_symbols {
+                // log beta_t(i) = log(sum(a_ij * b_j(O_t+1) * beta_t+1(j)))
+                for (j, term) in terms.iter_mut().enumerate() {
+                    *term = log_state_transition_matrix[i][j] + log_emission_probability_matrix[observation_index + 1][j] + self.beta_matrix[observation_index + 1][j];
+                }
+                self.beta_matrix[observation_index][i] = log_sum_exp2(&terms);
+            }
+        }
+
+        // An impossible sequence has no posterior, so it adds nothing rather than NaN
+        if self.log_probability == f64::NEG_INFINITY {
+            self.gamma_matrix.fill(0.0);
+            return;
+        }
+
+        for observation_index in 0..number_of_observations {
+            for i in 0..number_of_hidden_state_symbols {
+                // gamma_t(i) = alpha_t(i) * beta_t(i) / P(O | model)
+                self.gamma_matrix[observation_index][i] = (self.alpha_matrix[observation_index][i] + self.beta_matrix[observation_index][i] - self.log_probability).exp2();
+            }
+        }
+
+        if let Some(di_gamma_sums) = di_gamma_sums {
+            for observation_index in 0..(number_of_observations - 1) {
+                for i in 0..number_of_hidden_state_symbols {
+                    for j in 0..number_of_hidden_state_symbols {
+                        // di-gamma_t(i, j) = alpha_t(i) * a_ij * b_j(O_t+1) * beta_t+1(j) / P(O | model)
+                        di_gamma_sums[i][j] += (self.alpha_matrix[observation_index][i]
+                            + log_state_transition_matrix[i][j]
+                            + log_emission_probability_matrix[observation_index + 1][j]
+                            + self.beta_matrix[observation_index + 1][j]
+                            - self.log_probability)
+                            .exp2();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Takes log2 of every entry, so 0 becomes -inf.
+pub(crate) fn log2_matrix(matrix: &Matrix) -> Matrix {
+    let mut log_matrix = matrix.clone();
+    for row in log_matrix.rows_mut() {
+        for value in row.iter_mut() {
+            *value = value.log2();
+        }
+    }
+    log_matrix
+}
+
+/// log2(sum(2^x)) without leaving log space, and -inf when every term is.
+pub(crate) fn log_sum_exp2(log_values: &[f64]) -> f64 {
+    let max_log_value = log_values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    if max_log_value == f64::NEG_INFINITY {
+        return f64::NEG_INFINITY;
+    }
+    max_log_value + log_values.iter().map(|&log_value| (log_value - max_log_value).exp2()).sum::<f64>().log2()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{assert_matrices_close, matrix};
+
+    fn state_transition_matrix() -> Matrix {
+        matrix(&[&[0.8, 0.15, 0.05], &[0.2, 0.5, 0.3], &[0.1, 0.3, 0.6]])
+    }
+
+    const INITIAL_STATE_DISTRIBUTION_VECTOR: [f64; 3] = [0.5, 0.3, 0.2];
+
+    /// Row t holds b_i(O_t) for a fixed pseudo-random sequence of 3 symbols.
+    fn emission_probability_matrix(number_of_observations: usize) -> Matrix {
+        let transposed_observation_probability_matrix = matrix(&[&[0.6, 0.1, 0.3], &[0.3, 0.2, 0.1], &[0.1, 0.7, 0.6]]);
+        let mut emission_probability_matrix = Matrix::new(number_of_observations, 3, 0.0);
+        for (t, emission_probability_vector) in emission_probability_matrix.rows_mut().enumerate() {
+            emission_probability_vector.copy_from_slice(&transposed_observation_probability_matrix[(t * t + t / 3) % 3]);
+        }
+        emission_probability_matrix
+    }
+
+    fn run(emission_probability_matrix: &Matrix, arithmetic: ForwardBackwardArithmetic) -> (ForwardBackward, Matrix) {
+        let mut forward_backward = ForwardBackward::new(3, emission_probability_matrix.get_number_of_rows(), arithmetic);
+        let mut di_gamma_sums = Matrix::new(3, 3, 0.0);
+        forward_backward.compute(&state_transition_matrix(), &INITIAL_STATE_DISTRIBUTION_VECTOR, emission_probability_matrix, Some(&mut di_gamma_sums));
+        (forward_backward, di_gamma_sums)
+    }
+
+    #[test]
+    fn log_space_matches_scale_factors() {
+        let emission_probability_matrix = emission_probability_matrix(500);
+        let (scaled, scaled_di_gamma_sums) = run(&emission_probability_matrix, ForwardBackwardArithmetic::ScaleFactors);
+        let (log_space, log_space_di_gamma_sums) = run(&emission_probability_matrix, ForwardBackwardArithmetic::LogSpace);
+
+        assert!((scaled.log_probability() - log_space.log_probability()).abs() < 1e-9 * scaled.log_probability().abs());
+        // Log space rounds log alpha, which grows with T, so the posteriors agree to a little less than full precision
+        assert_matrices_close(&log_space.gamma_matrix, &scaled.gamma_matrix, 1e-10);
+        assert_matrices_close(&log_space_di_gamma_sums, &scaled_di_gamma_sums, 1e-8);
+    }
+
+    #[test]
+    fn log_space_survives_where_scale_factors_break_down() {
+        // Every state gives the third observation a subnormal probability, so 1 / sum(alpha) overflows
+        let mut emission_probability_matrix = emission_probability_matrix(10);
+        emission_probability_matrix[2].iter_mut().for_each(|probability| *probability = 1e-320);
+
+        let (scaled, _) = run(&emission_probability_matrix, ForwardBackwardArithmetic::ScaleFactors);
+        let (log_space, di_gamma_sums) = run(&emission_probability_matrix, ForwardBackwardArithmetic::LogSpace);
+
+        assert!(!scaled.log_probability().is_finite());
+        assert!(log_space.log_probability().is_finite());
+        for gamma_vector in log_space.gamma_matrix.rows() {
+            assert!((gamma_vector.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+        }
+        assert!((di_gamma_sums.rows().flatten().sum::<f64>() - 9.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn log_space_gives_an_impossible_sequence_no_posterior() {
+        let mut emission_probability_matrix = emission_probability_matrix(6);
+        emission_probability_matrix[3].iter_mut().for_each(|probability| *probability = 0.0);
+
+        let (log_space, di_gamma_sums) = run(&emission_probability_matrix, ForwardBackwardArithmetic::LogSpace);
+
+        assert_eq!(log_space.log_probability(), f64::NEG_INFINITY);
+        assert!(log_space.gamma_matrix.rows().flatten().all(|&probability| probability == 0.0));
+        assert!(di_gamma_sums.rows().flatten().all(|&count| count == 0.0));
+    }
+}
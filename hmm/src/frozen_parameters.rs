@@ -0,0 +1,164 @@
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::{HmmError, HmmModel, Matrix};
+
+/// One of the three parameter blocks of a model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParameterBlock {
+    /// A
+    StateTransitionMatrix,
+    /// B
+    ObservationProbabilityMatrix,
+    /// pi
+    InitialStateDistributionVector,
+}
+
+impl FromStr for ParameterBlock {
+    type Err = String;
+
+    /// Parses "A", "B" or "pi".
+    fn from_str(name: &str) -> Result<ParameterBlock, String> {
+        match name {
+            "A" => Ok(ParameterBlock::StateTransitionMatrix),
+            "B" => Ok(ParameterBlock::ObservationProbabilityMatrix),
+            "pi" => Ok(ParameterBlock::InitialStateDistributionVector),
+            _ => Err(format!("unknown parameter block {:?} (expected A, B or pi)", name)),
+        }
+    }
+}
+
+/// Parameter blocks that training uses as given instead of guessing and re-estimating them.
+///
+/// Built from `FrozenParameters::default()` (nothing frozen) by chaining setters, e.g.
+/// `FrozenParameters::default().state_transition_matrix(english_letter_digraph_matrix())`, or read from a model file.
+#[derive(Debug, Clone, Default)]
+pub struct FrozenParameters {
+    pub(crate) state_transition_matrix: Option<Matrix>,
+    pub(crate) observation_probability_matrix: Option<Matrix>,
+    pub(crate) initial_state_distribution_vector: Option<Box<[f64]>>,
+    pub(crate) state_transition_tensor: Option<Matrix>,
+}
+
+impl FrozenParameters {
+    pub fn state_transition_matrix(mut self, state_transition_matrix: Matrix) -> FrozenParameters {
+        self.state_transition_matrix = Some(state_transition_matrix);
+        self
+    }
+
+    pub fn observation_probability_matrix(mut self, observation_probability_matrix: Matrix) -> FrozenParameters {
+        self.observation_probability_matrix = Some(observation_probability_matrix);
+        self
+    }
+
+    pub fn initial_state_distribution_vector(mut self, initial_state_distribution_vector: Box<[f64]>) -> FrozenParameters {
+        self.initial_state_distribution_vector = Some(initial_state_distribution_vector);
+        self
+    }
+
+    /// Second-order transitions for `SecondOrderHmmModel`, where row i * N + j holds P(next state | i then j).
+    /// A frozen A is then only the first transition. First-order models refuse to train with a tensor set.
+    pub fn state_transition_tensor(mut self, state_transition_tensor: Matrix) -> FrozenParameters {
+        self.state_transition_tensor = Some(state_transition_tensor);
+        self
+    }
+
+    /// Freezes `blocks` at their values in a model file written by `HmmModel::save`.
+    pub fn load(path: &Path, blocks: &[ParameterBlock]) -> Result<FrozenParameters, HmmError> {
+        let model = HmmModel::load(path)?.model;
+
+        let mut frozen_parameters = FrozenParameters::default();
+        for block in blocks {
+            frozen_parameters = match block {
+                ParameterBlock::StateTransitionMatrix => frozen_parameters.state_transition_matrix(model.state_transition_matrix.clone()),
+                ParameterBlock::ObservationProbabilityMatrix => frozen_parameters.observation_probability_matrix(model.observation_probability_matrix.clone()),
+                ParameterBlock::InitialStateDistributionVector => frozen_parameters.initial_state_distribution_vector(model.initial_state_distribution_vector.clone()),
+            };
+        }
+
+        Ok(frozen_parameters)
+    }
+
+    pub fn is_frozen(&self, block: ParameterBlock) -> bool {
+        match block {
+            ParameterBlock::StateTransitionMatrix => self.state_transition_matrix.is_some(),
+            ParameterBlock::ObservationProbabilityMatrix => self.observation_probability_matrix.is_some(),
+            ParameterBlock::InitialStateDistributionVector => self.initial_state_distribution_vector.is_some(),
+        }
+    }
+
+    /// Checks that every frozen block fits a model with these dimensions.
+    pub(crate) fn check_shape(&self, number_of_hidden_state_symbols: usize, number_of_observation_symbols: usize) -> Result<(), HmmError> {
+        if let Some(state_transition_matrix) = &self.state_transition_matrix {
+            if state_transition_matrix.get_number_of_rows() != number_of_hidden_state_symbols || state_transition_matrix.get_number_of_columns() != number_of_hidden_state_symbols {
+                return Err(HmmError::ShapeMismatch(format!(
+                    "frozen A is {}x{}, expected {}x{}",
+                    state_transition_matrix.get_number_of_rows(),
+                    state_transition_matrix.get_number_of_columns(),
+                    number_of_hidden_state_symbols,
+                    number_of_hidden_state_symbols
+                )));
+            }
+        }
+        if let Some(observation_probability_matrix) = &self.observation_probability_matrix {
+            if observation_probability_matrix.get_number_of_rows() != number_of_hidden_state_symbols || observation_probability_matrix.get_number_of_columns() != number_of_observation_symbols {
+                return Err(HmmError::ShapeMismatch(format!(
+                    "frozen B is {}x{}, expected {}x{}",
+                    observation_probability_matrix.get_number_of_rows(),
+                    observation_probability_matrix.get_number_of_columns(),
+                    number_of_hidden_state_symbols,
+                    number_of_observation_symbols
+                )));
+            }
+        }
+        if let Some(initial_state_distribution_vector) = &self.initial_state_distribution_vector {
+            if initial_state_distribution_vector.len() != number_of_hidden_state_symbols {
+                return Err(HmmError::ShapeMismatch(format!(
+                    "frozen pi has {} entries, expected {}",
+                    initial_state_distribution_vector.len(),
+                    number_of_hidden_state_symbols
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{assert_close, assert_matrices_close, matrix, pseudo_random_observations, test_model};
+    use crate::TrainingConfig;
+
+    #[test]
+    fn frozen_blocks_are_never_re_estimated() {
+        let frozen_model = test_model();
+        let observations = pseudo_random_observations(200, 3, 1);
+        let config = TrainingConfig::default()
+            .iterations(5)
+            .seed(4)
+            .print_progress(false)
+            .frozen_parameters(FrozenParameters::default().state_transition_matrix(frozen_model.state_transition_matrix.clone()).observation_probability_matrix(frozen_model.observation_probability_matrix.clone()));
+
+        let model = HmmModel::train_model(2, 3, &observations, &config).unwrap();
+
+        assert_matrices_close(&model.state_transition_matrix, &frozen_model.state_transition_matrix, 0.0);
+        assert_matrices_close(&model.observation_probability_matrix, &frozen_model.observation_probability_matrix, 0.0);
+        // pi alone was trained, from a random guess
+        assert_close(&[model.initial_state_distribution_vector.iter().sum::<f64>()], &[1.0], 1e-12);
+        assert!(model.log_probability > f64::NEG_INFINITY);
+    }
+
+    #[test]
+    fn frozen_blocks_must_fit_the_model() {
+        let observations = pseudo_random_observations(20, 3, 1);
+        let config = TrainingConfig::default().iterations(1).print_progress(false);
+
+        let wrong_shape = config.clone().frozen_parameters(FrozenParameters::default().observation_probability_matrix(matrix(&[&[0.5, 0.5], &[0.5, 0.5]])));
+        assert!(matches!(HmmModel::train_model(2, 3, &observations, &wrong_shape), Err(HmmError::ShapeMismatch(_))));
+
+        let tensor = config.frozen_parameters(FrozenParameters::default().state_transition_tensor(matrix(&[&[0.5, 0.5], &[0.5, 0.5], &[0.5, 0.5], &[0.5, 0.5]])));
+        assert!(matches!(HmmModel::train_model(2, 3, &observations, &tensor), Err(HmmError::InvalidConfiguration(_))));
+    }
+}
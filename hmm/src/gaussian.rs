@@ -0,0 +1,593 @@
+use std::f64::consts::{LN_2, PI};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::expected_counts::{self, add_matrix, add_vector};
+use crate::forward_backward::{ForwardBackward, ForwardBackwardArithmetic};
+use crate::kmeans::k_means;
+use crate::viterbi::{find_viterbi_path, ViterbiPath};
+use crate::{baum_welch, HmmError, Matrix, ParameterBlock, SequenceScore, TrainingConfig};
+
+/// Variances are kept above this fraction of the whole data set's variance, so a state can't collapse onto a
+/// single observation and drive the likelihood to infinity.
+const VARIANCE_FLOOR_FRACTION: f64 = 1e-3;
+
+/// Lower bound on the variance floor, for dimensions where every observation is the same.
+const MIN_VARIANCE: f64 = 1e-12;
+
+/// Hidden Markov model whose states emit real-valued vectors from a mixture of K Gaussians with diagonal
+/// covariance. K = 1 is a single Gaussian per state.
+///
+/// Observations are a T x D matrix with one row per time step, so univariate data is a single column.
+#[derive(Debug, Clone)]
+pub struct GaussianHmmModel {
+    pub state_transition_matrix: Matrix,
+    /// Row i holds the weights of state i's K mixture components.
+    pub mixture_weight_matrix: Matrix,
+    /// Row i * K + k is the mean of component k of state i.
+    pub mean_matrix: Matrix,
+    /// Row i * K + k holds the variance of every dimension of component k of state i.
+    pub variance_matrix: Matrix,
+    pub initial_state_distribution_vector: Box<[f64]>,
+    /// log2 of the likelihood (a density, so it can be positive) from the final training iteration, or with
+    /// validation, under the returned parameters.
+    pub log_probability: f64,
+    /// log2 of the likelihood of the validation observations, if training was given them.
+    pub validation_log_probability: Option<f64>,
+    /// Baum-Welch iterations run by training.
+    pub iterations: usize,
+    pub number_of_training_observations: usize,
+    /// Seed of the random initial guesses; training again with `TrainingConfig::seed` set to it gives the same model.
+    pub seed: Option<u64>,
+}
+
+impl GaussianHmmModel {
+    /// Creates an untrained model with one Gaussian per state, where row i of the mean and variance matrices
+    /// belongs to state i.
+    pub fn new(state_transition_matrix: Matrix, mean_matrix: Matrix, variance_matrix: Matrix, initial_state_distribution_vector: Box<[f64]>) -> GaussianHmmModel {
+        let mixture_weight_matrix = Matrix::new(initial_state_distribution_vector.len(), 1, 1.0);
+        GaussianHmmModel::new_mixture(state_transition_matrix, mixture_weight_matrix, mean_matrix, variance_matrix, initial_state_distribution_vector)
+    }
+
+    /// Creates an untrained model with a Gaussian mixture per state, laid out as the fields describe.
+    pub fn new_mixture(state_transition_matrix: Matrix, mixture_weight_matrix: Matrix, mean_matrix: Matrix, variance_matrix: Matrix, initial_state_distribution_vector: Box<[f64]>) -> GaussianHmmModel {
+        GaussianHmmModel {
+            state_transition_matrix,
+            mixture_weight_matrix,
+            mean_matrix,
+            variance_matrix,
+            initial_state_distribution_vector,
+            log_probability: f64::NEG_INFINITY,
+            validation_log_probability: None,
+            iterations: 0,
+            number_of_training_observations: 0,
+            seed: None,
+        }
+    }
+
+    /// Trains A, pi and a single Gaussian per state with Baum-Welch.
+    ///
+    /// A and pi start from random guesses as in `HmmModel::train_model` and can be frozen the same way. The
+    /// Gaussians start from a k-means clustering of the observations, one cluster per state.
+    pub fn train_model(number_of_hidden_state_symbols: usize, observations: &Matrix, config: &TrainingConfig) -> Result<GaussianHmmModel, HmmError> {
+        GaussianHmmModel::train_mixture_model_on_sequences(number_of_hidden_state_symbols, 1, std::slice::from_ref(observations), config)
+    }
+
+    /// Trains on several independent observation sequences, which must all have the same number of columns.
+    /// Empty sequences are skipped.
+    pub fn train_model_on_sequences(number_of_hidden_state_symbols: usize, sequences: &[Matrix], config: &TrainingConfig) -> Result<GaussianHmmModel, HmmError> {
+        GaussianHmmModel::train_mixture_model_on_sequences(number_of_hidden_state_symbols, 1, sequences, config)
+    }
+
+    /// Trains A, pi and a mixture of `number_of_mixture_components` Gaussians per state with Baum-Welch.
+    ///
+    /// The observations are first clustered with k-means into one cluster per state, then each state's cluster
+    /// is clustered again into one cluster per component, which gives the starting means, variances and weights.
+    pub fn train_mixture_model(number_of_hidden_state_symbols: usize, number_of_mixture_components: usize, observations: &Matrix, config: &TrainingConfig) -> Result<GaussianHmmModel, HmmError> {
+        GaussianHmmModel::train_mixture_model_on_sequences(number_of_hidden_state_symbols, number_of_mixture_components, std::slice::from_ref(observations), config)
+    }
+
+    /// `train_mixture_model` on several independent observation sequences. The E-step runs on all CPU cores, as
+    /// in `HmmModel::train_model_on_sequences`.
+    pub fn train_mixture_model_on_sequences(number_of_hidden_state_symbols: usize, number_of_mixture_components: usize, sequences: &[Matrix], config: &TrainingConfig) -> Result<GaussianHmmModel, HmmError> {
+        if number_of_hidden_state_symbols == 0 || number_of_mixture_components == 0 {
+            return Err(HmmError::InvalidConfiguration("a model needs at least one hidden state and one mixture component per state".to_string()));
+        }
+        let frozen_parameters = &config.frozen_parameters;
+        if frozen_parameters.is_frozen(ParameterBlock::ObservationProbabilityMatrix) {
+            return Err(HmmError::InvalidConfiguration("a Gaussian model has no B to freeze".to_string()));
+        }
+        if frozen_parameters.state_transition_tensor.is_some() {
+            return Err(HmmError::InvalidConfiguration("a state transition tensor needs a SecondOrderHmmModel".to_string()));
+        }
+        if config.validation_sequence.is_some() {
+            return Err(HmmError::InvalidConfiguration("validation sequences are symbols, which a Gaussian model can't score; use validation observations".to_string()));
+        }
+        frozen_parameters.check_shape(number_of_hidden_state_symbols, 0)?;
+        let re_estimate_state_transition_matrix = !frozen_parameters.is_frozen(ParameterBlock::StateTransitionMatrix);
+        let re_estimate_initial_state_distribution_vector = !frozen_parameters.is_frozen(ParameterBlock::InitialStateDistributionVector);
+        let seed = config.seed.unwrap_or_else(|| rand::thread_rng().gen());
+
+        let sequences: Box<[&Matrix]> = sequences.iter().filter(|sequence| sequence.get_number_of_rows() > 0).collect();
+        if sequences.is_empty() {
+            return Err(HmmError::EmptySequence);
+        }
+        let number_of_dimensions = sequences[0].get_number_of_columns();
+        if sequences.iter().any(|sequence| sequence.get_number_of_columns() != number_of_dimensions) {
+            return Err(HmmError::ShapeMismatch("every observation sequence must have the same number of dimensions".to_string()));
+        }
+        if let Some(validation_observations) = &config.validation_observations {
+            if validation_observations.get_number_of_rows() > 0 && validation_observations.get_number_of_columns() != number_of_dimensions {
+                return Err(HmmError::ShapeMismatch(format!(
+                    "validation observations have {} dimensions, the training observations {}",
+                    validation_observations.get_number_of_columns(),
+                    number_of_dimensions
+                )));
+            }
+        }
+
+        let number_of_components = number_of_hidden_state_symbols * number_of_mixture_components;
+        let mut model = GaussianHmmModel::new_mixture(
+            frozen_parameters.state_transition_matrix.clone().unwrap_or_else(|| Matrix::new(number_of_hidden_state_symbols, number_of_hidden_state_symbols, 0.0)),
+            Matrix::new(number_of_hidden_state_symbols, number_of_mixture_components, 0.0),
+            Matrix::new(number_of_components, number_of_dimensions, 0.0),
+            Matrix::new(number_of_components, number_of_dimensions, 0.0),
+            frozen_parameters.initial_state_distribution_vector.clone().unwrap_or_else(|| vec![0.0; number_of_hidden_state_symbols].into_boxed_slice()),
+        );
+        model.number_of_training_observations = sequences.iter().map(|sequence| sequence.get_number_of_rows()).sum();
+        model.seed = Some(seed);
+        let mut sequence_chunks = expected_counts::chunk_sequences(
+            &sequences,
+            |sequence| sequence.get_number_of_rows(),
+            |sequence| ForwardBackward::new(number_of_hidden_state_symbols, sequence.get_number_of_rows(), config.forward_backward_arithmetic),
+        );
+
+        // Statistics are accumulated relative to the data mean so the variance update doesn't lose precision to
+        // cancellation when the data sits far from zero
+        let points: Vec<&[f64]> = sequences.iter().flat_map(|sequence| sequence.rows()).collect();
+        let (data_mean_vector, data_variance_vector) = mean_and_variance(&points, number_of_dimensions);
+        let variance_floor_vector: Box<[f64]> = data_variance_vector.iter().map(|&variance| (variance * VARIANCE_FLOOR_FRACTION).max(MIN_VARIANCE)).collect();
+
+        // Generate guesses
+        let mut rng = StdRng::seed_from_u64(seed);
+        for i in 0..number_of_hidden_state_symbols {
+            if re_estimate_state_transition_matrix {
+                baum_welch::draw_initial_values(&mut model.state_transition_matrix[i], &mut rng, config);
+                baum_welch::normalize(&mut model.state_transition_matrix[i]);
+            }
+            if re_estimate_initial_state_distribution_vector {
+                baum_welch::draw_initial_values(&mut model.initial_state_distribution_vector[i..=i], &mut rng, config);
+            }
+        }
+
+        // k-means for the states, then again inside each state's cluster for its components
+        let state_labels = k_means(&points, number_of_hidden_state_symbols, &mut rng);
+        for i in 0..number_of_hidden_state_symbols {
+            let mut state_points: Vec<&[f64]> = points.iter().zip(state_labels.iter()).filter(|&(_, &label)| label == i).map(|(&point, _)| point).collect();
+            if state_points.is_empty() {
+                // More states than distinct observations, so start this one from a random observation
+                state_points.push(points[rng.gen_range(0, points.len())]);
+            }
+
+            let component_labels = k_means(&state_points, number_of_mixture_components, &mut rng);
+            for k in 0..number_of_mixture_components {
+                let mut component_points: Vec<&[f64]> = state_points.iter().zip(component_labels.iter()).filter(|&(_, &label)| label == k).map(|(&point, _)| point).collect();
+                // Add-one smoothing keeps every component's weight above zero
+                model.mixture_weight_matrix[i][k] = (component_points.len() + 1) as f64 / (state_points.len() + number_of_mixture_components) as f64;
+
+                if component_points.is_empty() {
+                    component_points.push(state_points[rng.gen_range(0, state_points.len())]);
+                }
+                let (component_mean_vector, component_variance_vector) = mean_and_variance(&component_points, number_of_dimensions);
+                model.mean_matrix[i * number_of_mixture_components + k].copy_from_slice(&component_mean_vector);
+                for (d, variance) in model.variance_matrix[i * number_of_mixture_components + k].iter_mut().enumerate() {
+                    // A single point has no spread, so it gets the spread of the whole data
+                    *variance = match component_points.len() {
+                        1 => data_variance_vector[d],
+                        _ => component_variance_vector[d],
+                    }
+                    .max(variance_floor_vector[d]);
+                }
+            }
+        }
+        if re_estimate_initial_state_distribution_vector {
+            baum_welch::normalize(&mut model.initial_state_distribution_vector);
+        }
+
+        let run = baum_welch::run_baum_welch(
+            model,
+            config,
+            |model| {
+                let expected_counts = expected_counts::sum_over_chunks(
+                    &mut sequence_chunks,
+                    || GaussianExpectedCounts::new(number_of_hidden_state_symbols, number_of_components, number_of_dimensions),
+                    |expected_counts, forward_backward, observations| expected_counts.add_sequence(forward_backward, model, observations, &data_mean_vector),
+                    GaussianExpectedCounts::add,
+                );
+                let log_probability = expected_counts.log_probability;
+                (expected_counts, log_probability)
+            },
+            |model| match &config.validation_observations {
+                None => Ok(None),
+                Some(validation_observations) => Ok(Some(model.evaluate_with_arithmetic(validation_observations, config.forward_backward_arithmetic)?.log_probability)),
+            },
+            |model, expected_counts, iteration| {
+                if re_estimate_initial_state_distribution_vector {
+                    baum_welch::re_estimate_initial_state_distribution_vector(
+                        &mut model.initial_state_distribution_vector,
+                        &expected_counts.initial_state_counts,
+                        expected_counts.number_of_possible_sequences,
+                        config.initial_state_pseudocount,
+                    )?;
+                }
+                if re_estimate_state_transition_matrix {
+                    baum_welch::re_estimate_rows(
+                        &mut model.state_transition_matrix,
+                        &expected_counts.state_transition_counts,
+                        &expected_counts.state_transition_totals,
+                        config.state_transition_pseudocount,
+                        "transitions out of",
+                        iteration,
+                    )?;
+                }
+
+                for i in 0..number_of_hidden_state_symbols {
+                    // A state or component nothing is assigned to keeps its old parameters rather than becoming NaN
+                    if expected_counts.state_occupancies[i] == 0.0 {
+                        continue;
+                    }
+                    for k in 0..number_of_mixture_components {
+                        let component = i * number_of_mixture_components + k;
+                        let component_occupancy = expected_counts.component_occupancies[component];
+
+                        // c_ik = sum(gamma_t(i, k)) / sum(gamma_t(i))
+                        model.mixture_weight_matrix[i][k] = component_occupancy / expected_counts.state_occupancies[i];
+
+                        if component_occupancy == 0.0 {
+                            continue;
+                        }
+                        for d in 0..number_of_dimensions {
+                            // mu_ik = sum(gamma_t(i, k) * O_t) / sum(gamma_t(i, k))
+                            let mean_deviation = expected_counts.weighted_deviation_sums[component][d] / component_occupancy;
+                            model.mean_matrix[component][d] = data_mean_vector[d] + mean_deviation;
+
+                            // sigma_ik^2 = sum(gamma_t(i, k) * (O_t - mu_ik)^2) / sum(gamma_t(i, k))
+                            let variance = expected_counts.weighted_squared_deviation_sums[component][d] / component_occupancy - mean_deviation * mean_deviation;
+                            model.variance_matrix[component][d] = variance.max(variance_floor_vector[d]);
+                        }
+                    }
+                }
+
+                Ok(())
+            },
+        )?;
+
+        let mut model = run.model;
+        model.log_probability = run.log_probability;
+        model.validation_log_probability = run.validation_log_probability;
+        model.iterations = run.iterations;
+
+        Ok(model)
+    }
+
+    /// Scores `observations` with the scaled forward pass, scoring them again in log space if alpha vanishes.
+    pub fn evaluate(&self, observations: &Matrix) -> Result<SequenceScore, HmmError> {
+        self.evaluate_with_arithmetic(observations, ForwardBackwardArithmetic::ScaleFactors)
+    }
+
+    /// `evaluate` with forward-backward in log space from the start, or with scale factors.
+    pub fn evaluate_with_arithmetic(&self, observations: &Matrix, arithmetic: ForwardBackwardArithmetic) -> Result<SequenceScore, HmmError> {
+        self.check_dimensions(observations)?;
+        let number_of_observations = observations.get_number_of_rows();
+        if number_of_observations == 0 {
+            return Ok(SequenceScore::new(0.0, 0));
+        }
+
+        let (emission_probability_matrix, _, emission_log_scale) = self.scaled_emission_probabilities(observations);
+        let forward_backward = self.run_forward_backward(&emission_probability_matrix, arithmetic);
+
+        Ok(SequenceScore::new(forward_backward.log_probability() + emission_log_scale, number_of_observations))
+    }
+
+    /// Finds the most likely hidden state sequence for `observations` with the Viterbi algorithm.
+    pub fn viterbi(&self, observations: &Matrix) -> Result<ViterbiPath, HmmError> {
+        self.check_dimensions(observations)?;
+        let (emission_probability_matrix, _, emission_log_scale) = self.scaled_emission_probabilities(observations);
+
+        let mut viterbi_path = find_viterbi_path(&self.state_transition_matrix, &self.initial_state_distribution_vector, &emission_probability_matrix)?;
+        viterbi_path.log_probability += emission_log_scale;
+        Ok(viterbi_path)
+    }
+
+    /// Runs forward-backward under the current parameters and returns gamma, where row t holds
+    /// P(X_t = i | O, model) for every hidden state i. Every row is 0 if the observations are impossible, or so
+    /// unlikely that their densities underflow.
+    pub fn posterior_state_probabilities(&self, observations: &Matrix) -> Result<Matrix, HmmError> {
+        self.check_dimensions(observations)?;
+        if observations.get_number_of_rows() == 0 {
+            return Ok(Matrix::new(0, self.get_number_of_hidden_state_symbols(), 0.0));
+        }
+
+        let (emission_probability_matrix, _, _) = self.scaled_emission_probabilities(observations);
+        Ok(self.run_forward_backward(&emission_probability_matrix, ForwardBackwardArithmetic::ScaleFactors).gamma_matrix)
+    }
+
+    /// Forward-backward over a non-empty emission probability matrix, run again in log space if a scale factor is
+    /// 1 / 0, as `HmmModel::posterior_state_probabilities` does.
+    fn run_forward_backward(&self, emission_probability_matrix: &Matrix, arithmetic: ForwardBackwardArithmetic) -> ForwardBackward {
+        let number_of_observations = emission_probability_matrix.get_number_of_rows();
+        let mut forward_backward = ForwardBackward::new(self.get_number_of_hidden_state_symbols(), number_of_observations, arithmetic);
+        forward_backward.compute(&self.state_transition_matrix, &self.initial_state_distribution_vector, emission_probability_matrix, None);
+        if !forward_backward.log_probability().is_finite() && arithmetic == ForwardBackwardArithmetic::ScaleFactors {
+            forward_backward = ForwardBackward::new(self.get_number_of_hidden_state_symbols(), number_of_observations, ForwardBackwardArithmetic::LogSpace);
+            forward_backward.compute(&self.state_transition_matrix, &self.initial_state_distribution_vector, emission_probability_matrix, None);
+        }
+
+        forward_backward
+    }
+
+    /// log2 of the density of `observation` under state i's mixture.
+    pub fn log_emission_density(&self, i: usize, observation: &[f64]) -> f64 {
+        let log_component_densities: Box<[f64]> = (0..self.get_number_of_mixture_components())
+            .map(|k| self.mixture_weight_matrix[i][k].log2() + self.log_component_density(i * self.get_number_of_mixture_components() + k, observation))
+            .collect();
+
+        let max_log_component_density = log_component_densities.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        if max_log_component_density == f64::NEG_INFINITY {
+            return f64::NEG_INFINITY;
+        }
+        max_log_component_density + log_component_densities.iter().map(|&log_density| (log_density - max_log_component_density).exp2()).sum::<f64>().log2()
+    }
+
+    /// log2 N(observation; mu, sigma^2) for one row of the mean and variance matrices.
+    fn log_component_density(&self, component: usize, observation: &[f64]) -> f64 {
+        let mut log_density = 0.0;
+        for ((&value, &mean), &variance) in observation.iter().zip(self.mean_matrix[component].iter()).zip(self.variance_matrix[component].iter()) {
+            // ln N(x; mu, sigma^2) = -(ln(2 pi sigma^2) + (x - mu)^2 / sigma^2) / 2
+            log_density -= ((2.0 * PI * variance).ln() + (value - mean) * (value - mean) / variance) / 2.0;
+        }
+
+        log_density / LN_2
+    }
+
+    fn check_dimensions(&self, observations: &Matrix) -> Result<(), HmmError> {
+        if observations.get_number_of_rows() > 0 && observations.get_number_of_columns() != self.get_number_of_dimensions() {
+            return Err(HmmError::ShapeMismatch(format!(
+                "observations have {} dimensions, the model has {}",
+                observations.get_number_of_columns(),
+                self.get_number_of_dimensions()
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Densities b_i(O_t), and the weighted component densities c_ik * N_ik(O_t) they're the sum of, with each time
+    /// step divided by its largest component so high-dimensional observations don't underflow.
+    ///
+    /// Returns both matrices and the log2 of the product of the removed factors, which the caller adds back to any
+    /// log probability. Gamma, the responsibilities and the Viterbi path don't change, because everything at a time
+    /// step is scaled by the same factor.
+    fn scaled_emission_probabilities(&self, observations: &Matrix) -> (Matrix, Matrix, f64) {
+        let number_of_mixture_components = self.get_number_of_mixture_components();
+        let mut emission_probability_matrix = Matrix::new(observations.get_number_of_rows(), self.get_number_of_hidden_state_symbols(), 0.0);
+        let mut component_probability_matrix = Matrix::new(observations.get_number_of_rows(), self.mean_matrix.get_number_of_rows(), 0.0);
+        let mut emission_log_scale = 0.0;
+
+        for ((emission_probability_vector, component_probability_vector), observation_vector) in emission_probability_matrix.rows_mut().zip(component_probability_matrix.rows_mut()).zip(observations.rows()) {
+            for (component, component_probability) in component_probability_vector.iter_mut().enumerate() {
+                let mixture_weight = self.mixture_weight_matrix[component / number_of_mixture_components][component % number_of_mixture_components];
+                *component_probability = mixture_weight.log2() + self.log_component_density(component, observation_vector);
+            }
+
+            let max_log_density = component_probability_vector.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+            for component_probability in component_probability_vector.iter_mut() {
+                *component_probability = (*component_probability - max_log_density).exp2();
+            }
+            for (emission_probability, state_component_probability_vector) in emission_probability_vector.iter_mut().zip(component_probability_vector.chunks(number_of_mixture_components)) {
+                *emission_probability = state_component_probability_vector.iter().sum::<f64>();
+            }
+            emission_log_scale += max_log_density;
+        }
+
+        (emission_probability_matrix, component_probability_matrix, emission_log_scale)
+    }
+
+    pub fn get_number_of_hidden_state_symbols(&self) -> usize {
+        self.initial_state_distribution_vector.len()
+    }
+
+    pub fn get_number_of_mixture_components(&self) -> usize {
+        self.mixture_weight_matrix.get_number_of_columns()
+    }
+
+    pub fn get_number_of_dimensions(&self) -> usize {
+        self.mean_matrix.get_number_of_columns()
+    }
+}
+
+/// Expected counts from the E-step of a Gaussian model, summed over some of the training sequences.
+#[derive(Debug)]
+struct GaussianExpectedCounts {
+    initial_state_counts: Box<[f64]>,
+    state_transition_counts: Matrix,
+    state_transition_totals: Box<[f64]>,
+    state_occupancies: Box<[f64]>,
+    component_occupancies: Box<[f64]>,
+    /// Row i * K + k sums gamma_t(i, k) * (O_t - data mean).
+    weighted_deviation_sums: Matrix,
+    /// Row i * K + k sums gamma_t(i, k) * (O_t - data mean)^2.
+    weighted_squared_deviation_sums: Matrix,
+    /// Sequences with a likelihood above 0, the only ones gamma counts.
+    number_of_possible_sequences: usize,
+    /// log2 of the likelihood of the same sequences.
+    log_probability: f64,
+}
+
+impl GaussianExpectedCounts {
+    fn new(number_of_hidden_state_symbols: usize, number_of_components: usize, number_of_dimensions: usize) -> GaussianExpectedCounts {
+        GaussianExpectedCounts {
+            initial_state_counts: vec![0.0; number_of_hidden_state_symbols].into_boxed_slice(),
+            state_transition_counts: Matrix::new(number_of_hidden_state_symbols, number_of_hidden_state_symbols, 0.0),
+            state_transition_totals: vec![0.0; number_of_hidden_state_symbols].into_boxed_slice(),
+            state_occupancies: vec![0.0; number_of_hidden_state_symbols].into_boxed_slice(),
+            component_occupancies: vec![0.0; number_of_components].into_boxed_slice(),
+            weighted_deviation_sums: Matrix::new(number_of_components, number_of_dimensions, 0.0),
+            weighted_squared_deviation_sums: Matrix::new(number_of_components, number_of_dimensions, 0.0),
+            number_of_possible_sequences: 0,
+            log_probability: 0.0,
+        }
+    }
+
+    fn add_sequence(&mut self, forward_backward: &mut ForwardBackward, model: &GaussianHmmModel, observations: &Matrix, data_mean_vector: &[f64]) {
+        let number_of_mixture_components = model.get_number_of_mixture_components();
+        let (emission_probability_matrix, component_probability_matrix, emission_log_scale) = model.scaled_emission_probabilities(observations);
+        forward_backward.compute(&model.state_transition_matrix, &model.initial_state_distribution_vector, &emission_probability_matrix, Some(&mut self.state_transition_counts));
+
+        for (initial_state_count, &gamma_value) in self.initial_state_counts.iter_mut().zip(forward_backward.gamma_matrix[0].iter()) {
+            *initial_state_count += gamma_value;
+        }
+
+        let number_of_observations = observations.get_number_of_rows();
+        for (observation_index, observation_vector) in observations.rows().enumerate() {
+            for (i, &gamma_value) in forward_backward.gamma_matrix[observation_index].iter().enumerate() {
+                // From 0 to T-2
+                if observation_index < number_of_observations - 1 {
+                    self.state_transition_totals[i] += gamma_value;
+                }
+
+                // Every t, since unlike a transition an emission happens at T-1 too
+                self.state_occupancies[i] += gamma_value;
+
+                let emission_probability = emission_probability_matrix[observation_index][i];
+                if emission_probability == 0.0 {
+                    continue;
+                }
+                for k in 0..number_of_mixture_components {
+                    let component = i * number_of_mixture_components + k;
+
+                    // gamma_t(i, k) = gamma_t(i) * c_ik * N_ik(O_t) / b_i(O_t)
+                    let responsibility = gamma_value * component_probability_matrix[observation_index][component] / emission_probability;
+                    self.component_occupancies[component] += responsibility;
+                    for (d, (&observation, &data_mean)) in observation_vector.iter().zip(data_mean_vector.iter()).enumerate() {
+                        let deviation = observation - data_mean;
+                        self.weighted_deviation_sums[component][d] += responsibility * deviation;
+                        self.weighted_squared_deviation_sums[component][d] += responsibility * deviation * deviation;
+                    }
+                }
+            }
+        }
+
+        if forward_backward.log_probability() > f64::NEG_INFINITY {
+            self.number_of_possible_sequences += 1;
+        }
+        self.log_probability += forward_backward.log_probability() + emission_log_scale;
+    }
+
+    fn add(&mut self, other: &GaussianExpectedCounts) {
+        add_vector(&mut self.initial_state_counts, &other.initial_state_counts);
+        add_matrix(&mut self.state_transition_counts, &other.state_transition_counts);
+        add_vector(&mut self.state_transition_totals, &other.state_transition_totals);
+        add_vector(&mut self.state_occupancies, &other.state_occupancies);
+        add_vector(&mut self.component_occupancies, &other.component_occupancies);
+        add_matrix(&mut self.weighted_deviation_sums, &other.weighted_deviation_sums);
+        add_matrix(&mut self.weighted_squared_deviation_sums, &other.weighted_squared_deviation_sums);
+        self.number_of_possible_sequences += other.number_of_possible_sequences;
+        self.log_probability += other.log_probability;
+    }
+}
+
+/// Mean and (population) variance of every dimension over `points`.
+fn mean_and_variance(points: &[&[f64]], number_of_dimensions: usize) -> (Box<[f64]>, Box<[f64]>) {
+    let number_of_points = points.len() as f64;
+
+    let mut mean_vector = vec![0.0; number_of_dimensions].into_boxed_slice();
+    for point in points.iter() {
+        for (mean, &value) in mean_vector.iter_mut().zip(point.iter()) {
+            *mean += value / number_of_points;
+        }
+    }
+
+    let mut variance_vector = vec![0.0; number_of_dimensions].into_boxed_slice();
+    for point in points.iter() {
+        for ((variance, &value), &mean) in variance_vector.iter_mut().zip(point.iter()).zip(mean_vector.iter()) {
+            *variance += (value - mean) * (value - mean) / number_of_points;
+        }
+    }
+
+    (mean_vector, variance_vector)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::test_support::{matrix, pseudo_random_observations};
+    use crate::{TrainingControl, TrainingObserver};
+
+    /// Records the log probability of every iteration.
+    #[derive(Debug, Default)]
+    struct LogProbabilityRecorder {
+        log_probabilities: Mutex<Vec<f64>>,
+    }
+
+    impl TrainingObserver for LogProbabilityRecorder {
+        fn iteration_finished(&self, _iteration: usize, log_probability: f64) -> TrainingControl {
+            self.log_probabilities.lock().unwrap().push(log_probability);
+            TrainingControl::Continue
+        }
+    }
+
+    #[test]
+    fn posteriors_fall_back_to_log_space_instead_of_nan() {
+        // Only state 0 can start or be reached, and the last observation is so far from its mean that its scaled
+        // density underflows to 0
+        let model = GaussianHmmModel::new(matrix(&[&[1.0, 0.0], &[0.0, 1.0]]), matrix(&[&[0.0], &[100.0]]), matrix(&[&[1.0], &[1.0]]), vec![1.0, 0.0].into_boxed_slice());
+        let observations = matrix(&[&[0.0], &[0.5], &[100.0]]);
+
+        let gamma_matrix = model.posterior_state_probabilities(&observations).unwrap();
+
+        assert!(gamma_matrix.rows().flatten().all(|&probability| probability == 0.0));
+        assert_eq!(model.evaluate(&observations).unwrap().log_probability, f64::NEG_INFINITY);
+    }
+
+    #[test]
+    fn a_mixture_model_never_loses_log_probability() {
+        // State-like runs around 0 and 30, where the first run type switches between components at -5 and 5
+        let jitter = pseudo_random_observations(400, 10, 2);
+        let observations = Matrix::from_rows(&(0..400).map(|t| vec![if t / 20 % 2 == 1 { 30.0 } else if t % 3 == 0 { -5.0 } else { 5.0 } + jitter[t] as f64 / 10.0]).collect::<Vec<_>>()).unwrap();
+        let recorder = Arc::new(LogProbabilityRecorder::default());
+        let config = TrainingConfig::default().iterations(20).seed(5).observer(recorder.clone());
+
+        let model = GaussianHmmModel::train_mixture_model(2, 2, &observations, &config).unwrap();
+
+        let log_probabilities = recorder.log_probabilities.lock().unwrap().clone();
+        assert_eq!(log_probabilities.len(), 20);
+        for pair in log_probabilities.windows(2) {
+            assert!(pair[1] >= pair[0] - 1e-9, "log probability fell from {} to {}", pair[0], pair[1]);
+        }
+        for row in model.mixture_weight_matrix.rows() {
+            assert!((row.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn training_recovers_well_separated_means() {
+        // Runs of 20 observations that alternate between 0 and 10, each jittered by less than 0.5
+        let jitter = pseudo_random_observations(400, 10, 1);
+        let observations = Matrix::from_rows(&(0..400).map(|t| vec![(t / 20 % 2) as f64 * 10.0 + jitter[t] as f64 / 10.0 - 0.45]).collect::<Vec<_>>()).unwrap();
+        let config = TrainingConfig::default().iterations(20).seed(3).print_progress(false);
+
+        let model = GaussianHmmModel::train_model(2, &observations, &config).unwrap();
+
+        let low_state = if model.mean_matrix[0][0] < model.mean_matrix[1][0] { 0 } else { 1 };
+        assert!(model.mean_matrix[low_state][0].abs() < 0.1, "{:?}", model.mean_matrix);
+        assert!((model.mean_matrix[1 - low_state][0] - 10.0).abs() < 0.1, "{:?}", model.mean_matrix);
+        // Staying in a run is 19 times as likely as leaving it
+        assert!((model.state_transition_matrix[low_state][low_state] - 0.95).abs() < 0.02, "{:?}", model.state_transition_matrix);
+        let state_path = model.viterbi(&observations).unwrap().state_path;
+        assert!(state_path.iter().enumerate().all(|(t, &state)| (state == low_state) == (t / 20 % 2 == 0)));
+    }
+}
@@ -0,0 +1,106 @@
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+
+/// Same cap as chapter6/kmeans.py.
+const MAX_K_MEANS_ITERATIONS: usize = 150;
+
+/// Splits `points` into `number_of_clusters` clusters with Lloyd's algorithm and returns the cluster of every point.
+///
+/// Starts the way chapter6/kmeans.py does: the points are shuffled and split into equal chunks, whose means are
+/// the first centers. A cluster that loses all its points stays empty.
+pub(crate) fn k_means(points: &[&[f64]], number_of_clusters: usize, rng: &mut StdRng) -> Box<[usize]> {
+    let mut shuffled_point_indices: Vec<usize> = (0..points.len()).collect();
+    shuffled_point_indices.shuffle(rng);
+
+    let mut labels = vec![0; points.len()].into_boxed_slice();
+    for (position, &point_index) in shuffled_point_indices.iter().enumerate() {
+        labels[point_index] = position * number_of_clusters / points.len();
+    }
+
+    for _ in 0..MAX_K_MEANS_ITERATIONS {
+        let centers = cluster_centers(points, &labels, number_of_clusters);
+
+        let mut changed = false;
+        for (label, point) in labels.iter_mut().zip(points.iter()) {
+            let mut best_cluster = *label;
+            let mut best_distance = f64::INFINITY;
+            for (cluster, center) in centers.iter().enumerate() {
+                if let Some(center) = center {
+                    // Squared Euclidean distance picks the same cluster as the Euclidean distance
+                    let distance = point.iter().zip(center.iter()).map(|(&value, &center_value)| (value - center_value) * (value - center_value)).sum::<f64>();
+                    if distance < best_distance {
+                        best_cluster = cluster;
+                        best_distance = distance;
+                    }
+                }
+            }
+
+            changed |= best_cluster != *label;
+            *label = best_cluster;
+        }
+
+        if !changed {
+            break;
+        }
+    }
+
+    labels
+}
+
+/// Mean of each cluster's points, or `None` for an empty cluster.
+fn cluster_centers(points: &[&[f64]], labels: &[usize], number_of_clusters: usize) -> Vec<Option<Box<[f64]>>> {
+    let number_of_dimensions = points.first().map_or(0, |point| point.len());
+    let mut sums = vec![vec![0.0; number_of_dimensions].into_boxed_slice(); number_of_clusters];
+    let mut counts = vec![0; number_of_clusters];
+
+    for (&label, point) in labels.iter().zip(points.iter()) {
+        for (sum, &value) in sums[label].iter_mut().zip(point.iter()) {
+            *sum += value;
+        }
+        counts[label] += 1;
+    }
+
+    sums.into_iter()
+        .zip(counts)
+        .map(|(sum, count)| match count {
+            0 => None,
+            _ => Some(sum.iter().map(|&value| value / count as f64).collect()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    fn k_means_separates_clear_clusters() {
+        let points: Vec<[f64; 2]> = (0..30).map(|n| [(n % 3) as f64 * 10.0 + (n % 5) as f64 * 0.1, (n % 3) as f64 * -10.0 + (n % 7) as f64 * 0.1]).collect();
+        let points: Vec<&[f64]> = points.iter().map(|point| &point[..]).collect();
+
+        let labels = k_means(&points, 3, &mut StdRng::seed_from_u64(1));
+
+        // Points n and n + 3 come from the same cluster, and the three clusters get different labels
+        for n in 3..points.len() {
+            assert_eq!(labels[n], labels[n - 3]);
+        }
+        let mut first_labels = labels[0..3].to_vec();
+        first_labels.sort_unstable();
+        assert_eq!(first_labels, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn a_cluster_can_stay_empty() {
+        // Two distinct points can't fill three clusters
+        let points: Vec<&[f64]> = vec![&[0.0], &[0.0], &[5.0], &[5.0]];
+
+        let labels = k_means(&points, 3, &mut StdRng::seed_from_u64(1));
+
+        assert_eq!(labels[0], labels[1]);
+        assert_eq!(labels[2], labels[3]);
+        assert_ne!(labels[0], labels[2]);
+        assert!(labels.iter().all(|&label| label < 3));
+    }
+}
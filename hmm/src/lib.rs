@@ -0,0 +1,46 @@
+//! Hidden Markov models with discrete or Gaussian mixture emissions, and second-order models, trained with the Baum-Welch algorithm.
+//!
+//! Shared by every chapter 2 experiment so that a fix to the trainer reaches all of them.
+
+mod baum_welch;
+mod cli;
+mod english;
+mod error;
+mod evaluation;
+mod expected_counts;
+mod forward_backward;
+mod frozen_parameters;
+mod gaussian;
+mod kmeans;
+mod matrix;
+mod model;
+mod model_file;
+mod model_selection;
+mod observer;
+mod posterior;
+mod restarts;
+mod sampling;
+mod second_order;
+#[cfg(test)]
+mod test_support;
+mod text;
+mod training_config;
+mod viterbi;
+
+pub use cli::{parse_positional_arg, parse_training_args, TRAINING_FLAGS_USAGE};
+pub use english::{english_letter_digraph_matrix, english_letter_trigram_tensor, ENGLISH_LETTER_DIGRAPH_MATRIX};
+pub use error::HmmError;
+pub use evaluation::SequenceScore;
+pub use forward_backward::ForwardBackwardArithmetic;
+pub use frozen_parameters::{FrozenParameters, ParameterBlock};
+pub use gaussian::GaussianHmmModel;
+pub use matrix::Matrix;
+pub use model::HmmModel;
+pub use model_file::{SavedModel, TrainingMetadata, MODEL_FILE_FORMAT_VERSION};
+pub use model_selection::{InformationCriterion, ModelSelection, ModelSelectionCandidate};
+pub use observer::{ProgressPrinter, SilentObserver, TrainingControl, TrainingObserver};
+pub use sampling::SampledSequence;
+pub use second_order::SecondOrderHmmModel;
+pub use text::{normalize_text, text_to_observations, LETTERS_AND_SPACE};
+pub use training_config::TrainingConfig;
+pub use viterbi::ViterbiPath;
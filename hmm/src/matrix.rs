@@ -0,0 +1,97 @@
+use std::ops::{Index, IndexMut};
+
+/// Dense row-major matrix kept in a single allocation.
+///
+/// `matrix[i]` is row i as a slice, so `matrix[i][j]` reads the same as it did for boxed rows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Matrix {
+    number_of_rows: usize,
+    number_of_columns: usize,
+    values: Box<[f64]>,
+}
+
+impl Matrix {
+    pub fn new(number_of_rows: usize, number_of_columns: usize, value: f64) -> Matrix {
+        Matrix {
+            number_of_rows,
+            number_of_columns,
+            values: vec![value; number_of_rows * number_of_columns].into_boxed_slice(),
+        }
+    }
+
+    /// Copies nested rows into a matrix. Returns `None` if the rows differ in length.
+    pub fn from_rows<R: AsRef<[f64]>>(rows: &[R]) -> Option<Matrix> {
+        let number_of_columns = rows.first().map_or(0, |row| row.as_ref().len());
+        if rows.iter().any(|row| row.as_ref().len() != number_of_columns) {
+            return None;
+        }
+
+        Some(Matrix {
+            number_of_rows: rows.len(),
+            number_of_columns,
+            values: rows.iter().flat_map(|row| row.as_ref().iter().cloned()).collect(),
+        })
+    }
+
+    /// One value per row, e.g. a univariate observation sequence.
+    pub fn from_column(values: &[f64]) -> Matrix {
+        Matrix {
+            number_of_rows: values.len(),
+            number_of_columns: 1,
+            values: values.into(),
+        }
+    }
+
+    pub fn to_rows(&self) -> Vec<Vec<f64>> {
+        self.rows().map(|row| row.to_vec()).collect()
+    }
+
+    pub fn get_number_of_rows(&self) -> usize {
+        self.number_of_rows
+    }
+
+    pub fn get_number_of_columns(&self) -> usize {
+        self.number_of_columns
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[f64]> {
+        // chunks(0) panics, and a matrix without columns has no values to split anyway
+        self.values.chunks(self.number_of_columns.max(1)).take(self.number_of_rows)
+    }
+
+    pub fn rows_mut(&mut self) -> impl Iterator<Item = &mut [f64]> {
+        let number_of_rows = self.number_of_rows;
+        self.values.chunks_mut(self.number_of_columns.max(1)).take(number_of_rows)
+    }
+
+    pub fn transpose(&self) -> Matrix {
+        let mut transposed_matrix = Matrix::new(self.number_of_columns, self.number_of_rows, 0.0);
+        for (i, row) in self.rows().enumerate() {
+            for (j, &value) in row.iter().enumerate() {
+                transposed_matrix[j][i] = value;
+            }
+        }
+
+        transposed_matrix
+    }
+
+    pub fn fill(&mut self, value: f64) {
+        for matrix_value in self.values.iter_mut() {
+            *matrix_value = value;
+        }
+    }
+}
+
+impl Index<usize> for Matrix {
+    type Output = [f64];
+
+    fn index(&self, row: usize) -> &[f64] {
+        &self.values[row * self.number_of_columns..(row + 1) * self.number_of_columns]
+    }
+}
+
+impl IndexMut<usize> for Matrix {
+    fn index_mut(&mut self, row: usize) -> &mut [f64] {
+        &mut self.values[row * self.number_of_columns..(row + 1) * self.number_of_columns]
+    }
+}
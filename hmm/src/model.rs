@@ -0,0 +1,221 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::error::check_symbols;
+use crate::{baum_welch, expected_counts};
+use crate::{HmmError, Matrix, ParameterBlock, TrainingConfig};
+
+#[derive(Debug, Clone)]
+pub struct HmmModel {
+    pub state_transition_matrix: Matrix,
+    pub observation_probability_matrix: Matrix,
+    pub initial_state_distribution_vector: Box<[f64]>,
+    /// log2 P(O | model) from the final training iteration, or with validation, under the returned parameters.
+    pub log_probability: f64,
+    /// log2 P(validation sequence | model), if training was given one.
+    pub validation_log_probability: Option<f64>,
+    /// Baum-Welch iterations run by training.
+    pub iterations: usize,
+    pub number_of_training_observations: usize,
+    /// Seed of the random initial guesses; training again with `TrainingConfig::seed` set to it gives the same model.
+    pub seed: Option<u64>,
+}
+
+impl HmmModel {
+    /// Creates an untrained model from known parameters.
+    pub fn new(state_transition_matrix: Matrix, observation_probability_matrix: Matrix, initial_state_distribution_vector: Box<[f64]>) -> HmmModel {
+        HmmModel {
+            state_transition_matrix,
+            observation_probability_matrix,
+            initial_state_distribution_vector,
+            log_probability: f64::NEG_INFINITY,
+            validation_log_probability: None,
+            iterations: 0,
+            number_of_training_observations: 0,
+            seed: None,
+        }
+    }
+
+    /// Trains A, B and pi with Baum-Welch, starting from random guesses.
+    ///
+    /// Blocks set in `config`'s frozen parameters are used as given and never re-estimated. Fails if a frozen
+    /// block or an observation doesn't fit the model, or if an expected count vanishes so that a row of the
+    /// parameters can't be re-estimated (a pseudocount prevents that).
+    pub fn train_model(number_of_hidden_state_symbols: usize, number_of_observation_symbols: usize, observations: &[usize], config: &TrainingConfig) -> Result<HmmModel, HmmError> {
+        HmmModel::train(number_of_hidden_state_symbols, number_of_observation_symbols, &[observations], config)
+    }
+
+    /// Trains A, B and pi on several independent observation sequences, e.g. the documents of a corpus.
+    ///
+    /// Expected counts are summed over the sequences, so no transition is assumed between the end of one
+    /// sequence and the start of the next. Empty sequences are skipped. The E-step runs on all CPU cores, each
+    /// taking a chunk of consecutive sequences.
+    pub fn train_model_on_sequences<S: AsRef<[usize]>>(number_of_hidden_state_symbols: usize, number_of_observation_symbols: usize, sequences: &[S], config: &TrainingConfig) -> Result<HmmModel, HmmError> {
+        let sequences: Box<[&[usize]]> = sequences.iter().map(|sequence| sequence.as_ref()).collect();
+        HmmModel::train(number_of_hidden_state_symbols, number_of_observation_symbols, &sequences, config)
+    }
+
+    fn train(number_of_hidden_state_symbols: usize, number_of_observation_symbols: usize, sequences: &[&[usize]], config: &TrainingConfig) -> Result<HmmModel, HmmError> {
+        if number_of_hidden_state_symbols == 0 || number_of_observation_symbols == 0 {
+            return Err(HmmError::InvalidConfiguration("a model needs at least one hidden state and one observation symbol".to_string()));
+        }
+        let frozen_parameters = &config.frozen_parameters;
+        frozen_parameters.check_shape(number_of_hidden_state_symbols, number_of_observation_symbols)?;
+        if frozen_parameters.state_transition_tensor.is_some() {
+            return Err(HmmError::InvalidConfiguration("a state transition tensor needs a SecondOrderHmmModel".to_string()));
+        }
+        let re_estimate_state_transition_matrix = !frozen_parameters.is_frozen(ParameterBlock::StateTransitionMatrix);
+        let re_estimate_observation_probability_matrix = !frozen_parameters.is_frozen(ParameterBlock::ObservationProbabilityMatrix);
+        let re_estimate_initial_state_distribution_vector = !frozen_parameters.is_frozen(ParameterBlock::InitialStateDistributionVector);
+        let seed = config.seed.unwrap_or_else(|| rand::thread_rng().gen());
+
+        let sequences: Box<[&[usize]]> = sequences.iter().filter(|sequence| !sequence.is_empty()).cloned().collect();
+        if sequences.is_empty() {
+            return Err(HmmError::EmptySequence);
+        }
+        for sequence in sequences.iter() {
+            check_symbols(sequence, number_of_observation_symbols)?;
+        }
+        if let Some(validation_sequence) = &config.validation_sequence {
+            check_symbols(validation_sequence, number_of_observation_symbols)?;
+        }
+        if config.validation_observations.is_some() {
+            return Err(HmmError::InvalidConfiguration("validation observations are real-valued, which needs a GaussianHmmModel".to_string()));
+        }
+
+        let mut model = HmmModel::new(
+            frozen_parameters.state_transition_matrix.clone().unwrap_or_else(|| Matrix::new(number_of_hidden_state_symbols, number_of_hidden_state_symbols, 0.0)),
+            frozen_parameters.observation_probability_matrix.clone().unwrap_or_else(|| Matrix::new(number_of_hidden_state_symbols, number_of_observation_symbols, 0.0)),
+            frozen_parameters.initial_state_distribution_vector.clone().unwrap_or_else(|| vec![0.0; number_of_hidden_state_symbols].into_boxed_slice()),
+        );
+        model.number_of_training_observations = sequences.iter().map(|sequence| sequence.len()).sum();
+        model.seed = Some(seed);
+        let mut sequence_chunks = expected_counts::chunk_symbol_sequences(&sequences, number_of_hidden_state_symbols, config.forward_backward_arithmetic);
+
+        // Generate guesses
+        let mut rng = StdRng::seed_from_u64(seed);
+        for i in 0..number_of_hidden_state_symbols {
+            if re_estimate_state_transition_matrix {
+                baum_welch::draw_initial_values(&mut model.state_transition_matrix[i], &mut rng, config);
+            }
+            if re_estimate_observation_probability_matrix {
+                baum_welch::draw_initial_values(&mut model.observation_probability_matrix[i], &mut rng, config);
+            }
+            if re_estimate_initial_state_distribution_vector {
+                baum_welch::draw_initial_values(&mut model.initial_state_distribution_vector[i..=i], &mut rng, config);
+            }
+        }
+
+        // Normalize
+        for i in 0..number_of_hidden_state_symbols {
+            if re_estimate_state_transition_matrix {
+                baum_welch::normalize(&mut model.state_transition_matrix[i]);
+            }
+            if re_estimate_observation_probability_matrix {
+                baum_welch::normalize(&mut model.observation_probability_matrix[i]);
+            }
+        }
+        if re_estimate_initial_state_distribution_vector {
+            baum_welch::normalize(&mut model.initial_state_distribution_vector);
+        }
+
+        let run = baum_welch::run_baum_welch(
+            model,
+            config,
+            |model| {
+                let expected_counts = expected_counts::compute_expected_counts(&mut sequence_chunks, model, config.exclude_final_emission);
+                let log_probability = expected_counts.log_probability;
+                (expected_counts, log_probability)
+            },
+            |model| match &config.validation_sequence {
+                None => Ok(None),
+                Some(validation_sequence) => Ok(Some(model.evaluate_with_arithmetic(validation_sequence, config.forward_backward_arithmetic)?.log_probability)),
+            },
+            |model, expected_counts, iteration| {
+                if re_estimate_initial_state_distribution_vector {
+                    baum_welch::re_estimate_initial_state_distribution_vector(
+                        &mut model.initial_state_distribution_vector,
+                        &expected_counts.initial_state_counts,
+                        expected_counts.number_of_possible_sequences,
+                        config.initial_state_pseudocount,
+                    )?;
+                }
+                if re_estimate_state_transition_matrix {
+                    baum_welch::re_estimate_rows(
+                        &mut model.state_transition_matrix,
+                        &expected_counts.state_transition_counts,
+                        &expected_counts.state_transition_totals,
+                        config.state_transition_pseudocount,
+                        "transitions out of",
+                        iteration,
+                    )?;
+                }
+                if re_estimate_observation_probability_matrix {
+                    baum_welch::re_estimate_rows(
+                        &mut model.observation_probability_matrix,
+                        &expected_counts.observation_counts,
+                        &expected_counts.observation_totals,
+                        config.observation_pseudocount,
+                        "emissions from",
+                        iteration,
+                    )?;
+                }
+                Ok(())
+            },
+        )?;
+
+        let mut model = run.model;
+        model.log_probability = run.log_probability;
+        model.validation_log_probability = run.validation_log_probability;
+        model.iterations = run.iterations;
+
+        Ok(model)
+    }
+
+    /// Looks up b_i(O_t) for every position t and state i, as the T x N matrix forward-backward and Viterbi work on.
+    pub fn emission_probability_matrix(&self, observations: &[usize]) -> Result<Matrix, HmmError> {
+        self.check_parameter_shapes()?;
+        check_symbols(observations, self.get_number_of_observation_symbols())?;
+        Ok(self.lookup_emission_probabilities(observations))
+    }
+
+    /// `emission_probability_matrix` for observations already known to be in range.
+    pub(crate) fn lookup_emission_probabilities(&self, observations: &[usize]) -> Matrix {
+        // Row O_t of the transpose is exactly row t of the result
+        let transposed_observation_probability_matrix = self.observation_probability_matrix.transpose();
+
+        let mut emission_probability_matrix = Matrix::new(observations.len(), self.get_number_of_hidden_state_symbols(), 0.0);
+        for (emission_probability_vector, &observation) in emission_probability_matrix.rows_mut().zip(observations.iter()) {
+            emission_probability_vector.copy_from_slice(&transposed_observation_probability_matrix[observation]);
+        }
+
+        emission_probability_matrix
+    }
+
+    /// Checks that A is N x N and B has N rows, where N is the length of pi, since `new` takes any matrices.
+    pub(crate) fn check_parameter_shapes(&self) -> Result<(), HmmError> {
+        let number_of_hidden_state_symbols = self.get_number_of_hidden_state_symbols();
+        if self.state_transition_matrix.get_number_of_rows() != number_of_hidden_state_symbols
+            || self.state_transition_matrix.get_number_of_columns() != number_of_hidden_state_symbols
+            || self.observation_probability_matrix.get_number_of_rows() != number_of_hidden_state_symbols
+        {
+            return Err(HmmError::ShapeMismatch(format!(
+                "A is {}x{} and B has {} rows, but pi has {} entries",
+                self.state_transition_matrix.get_number_of_rows(),
+                self.state_transition_matrix.get_number_of_columns(),
+                self.observation_probability_matrix.get_number_of_rows(),
+                number_of_hidden_state_symbols
+            )));
+        }
+
+        Ok(())
+    }
+
+    pub fn get_number_of_hidden_state_symbols(&self) -> usize {
+        self.initial_state_distribution_vector.len()
+    }
+
+    pub fn get_number_of_observation_symbols(&self) -> usize {
+        self.observation_probability_matrix.get_number_of_columns()
+    }
+}
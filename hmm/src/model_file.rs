@@ -0,0 +1,253 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{HmmError, HmmModel, Matrix};
+
+/// Bumped whenever the layout of the model file changes.
+pub const MODEL_FILE_FORMAT_VERSION: u32 = 1;
+
+/// Row sums may differ from 1 by this much before a matrix is rejected.
+const ROW_SUM_TOLERANCE: f64 = 1e-6;
+
+/// How a saved model was trained.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrainingMetadata {
+    pub iterations: usize,
+    /// log2 P(O | model) from the final training iteration, or with validation, under the saved parameters.
+    #[serde(with = "log_probability_json")]
+    pub log_probability: f64,
+    /// log2 P(validation sequence | model), if training was given one.
+    #[serde(default, with = "optional_log_probability_json")]
+    pub validation_log_probability: Option<f64>,
+    pub number_of_training_observations: usize,
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+/// A trained model together with the alphabet its observation symbols index into.
+#[derive(Debug)]
+pub struct SavedModel {
+    pub model: HmmModel,
+    /// Observation symbol j is the j-th character.
+    pub symbols: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ModelFile {
+    format_version: u32,
+    symbols: String,
+    state_transition_matrix: Vec<Vec<f64>>,
+    observation_probability_matrix: Vec<Vec<f64>>,
+    initial_state_distribution_vector: Box<[f64]>,
+    training: TrainingMetadata,
+}
+
+impl HmmModel {
+    /// Writes A, B, pi, the symbol alphabet and training metadata to `path` as JSON.
+    pub fn save(&self, path: &Path, symbols: &str) -> Result<(), HmmError> {
+        validate_parameters(&self.state_transition_matrix, &self.observation_probability_matrix, &self.initial_state_distribution_vector, symbols)?;
+
+        let model_file = ModelFile {
+            format_version: MODEL_FILE_FORMAT_VERSION,
+            symbols: symbols.to_string(),
+            state_transition_matrix: self.state_transition_matrix.to_rows(),
+            observation_probability_matrix: self.observation_probability_matrix.to_rows(),
+            initial_state_distribution_vector: self.initial_state_distribution_vector.clone(),
+            training: TrainingMetadata {
+                iterations: self.iterations,
+                log_probability: self.log_probability,
+                validation_log_probability: self.validation_log_probability,
+                number_of_training_observations: self.number_of_training_observations,
+                seed: self.seed,
+            },
+        };
+
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(writer, &model_file)?;
+
+        Ok(())
+    }
+
+    /// Reads a model written by `save`, checking that every matrix has the right shape and is row stochastic.
+    pub fn load(path: &Path) -> Result<SavedModel, HmmError> {
+        let reader = BufReader::new(File::open(path)?);
+        let model_file: ModelFile = serde_json::from_reader(reader)?;
+
+        if model_file.format_version != MODEL_FILE_FORMAT_VERSION {
+            return Err(HmmError::UnsupportedFormatVersion(model_file.format_version));
+        }
+        let state_transition_matrix = Matrix::from_rows(&model_file.state_transition_matrix).ok_or_else(|| HmmError::ShapeMismatch("rows of A differ in length".to_string()))?;
+        let observation_probability_matrix = Matrix::from_rows(&model_file.observation_probability_matrix).ok_or_else(|| HmmError::ShapeMismatch("rows of B differ in length".to_string()))?;
+        validate_parameters(&state_transition_matrix, &observation_probability_matrix, &model_file.initial_state_distribution_vector, &model_file.symbols)?;
+
+        let mut model = HmmModel::new(state_transition_matrix, observation_probability_matrix, model_file.initial_state_distribution_vector);
+        model.iterations = model_file.training.iterations;
+        model.log_probability = model_file.training.log_probability;
+        model.validation_log_probability = model_file.training.validation_log_probability;
+        model.number_of_training_observations = model_file.training.number_of_training_observations;
+        model.seed = model_file.training.seed;
+
+        Ok(SavedModel {
+            model,
+            symbols: model_file.symbols,
+        })
+    }
+}
+
+/// A log probability as JSON, which has no infinities: serde_json would write the -inf of an untrained model or
+/// an impossible validation sequence as null and then refuse to read it back, so a non-finite value is written
+/// as the string "-inf", "inf" or "NaN" instead.
+struct JsonLogProbability(f64);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonNumberOrString {
+    Number(f64),
+    String(String),
+}
+
+impl Serialize for JsonLogProbability {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.0.is_finite() {
+            serializer.serialize_f64(self.0)
+        } else {
+            serializer.serialize_str(&self.0.to_string())
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for JsonLogProbability {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<JsonLogProbability, D::Error> {
+        match JsonNumberOrString::deserialize(deserializer)? {
+            JsonNumberOrString::Number(value) => Ok(JsonLogProbability(value)),
+            JsonNumberOrString::String(text) => text.parse().map(JsonLogProbability).map_err(|_| D::Error::custom(format!("invalid log probability {:?}", text))),
+        }
+    }
+}
+
+mod log_probability_json {
+    use super::*;
+
+    pub(super) fn serialize<S: Serializer>(log_probability: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+        JsonLogProbability(*log_probability).serialize(serializer)
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+        Ok(JsonLogProbability::deserialize(deserializer)?.0)
+    }
+}
+
+mod optional_log_probability_json {
+    use super::*;
+
+    pub(super) fn serialize<S: Serializer>(log_probability: &Option<f64>, serializer: S) -> Result<S::Ok, S::Error> {
+        log_probability.map(JsonLogProbability).serialize(serializer)
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+        Ok(Option::<JsonLogProbability>::deserialize(deserializer)?.map(|log_probability| log_probability.0))
+    }
+}
+
+fn validate_parameters(state_transition_matrix: &Matrix, observation_probability_matrix: &Matrix, initial_state_distribution_vector: &[f64], symbols: &str) -> Result<(), HmmError> {
+    let number_of_hidden_state_symbols = initial_state_distribution_vector.len();
+    let number_of_observation_symbols = symbols.chars().count();
+
+    if number_of_hidden_state_symbols == 0 {
+        return Err(HmmError::ShapeMismatch("model has no hidden states".to_string()));
+    }
+    if state_transition_matrix.get_number_of_rows() != number_of_hidden_state_symbols || observation_probability_matrix.get_number_of_rows() != number_of_hidden_state_symbols {
+        return Err(HmmError::ShapeMismatch(format!(
+            "expected {} rows in A and B, found {} and {}",
+            number_of_hidden_state_symbols,
+            state_transition_matrix.get_number_of_rows(),
+            observation_probability_matrix.get_number_of_rows()
+        )));
+    }
+
+    validate_row("pi", initial_state_distribution_vector, number_of_hidden_state_symbols)?;
+    for (i, row) in state_transition_matrix.rows().enumerate() {
+        validate_row(&format!("A row {}", i), row, number_of_hidden_state_symbols)?;
+    }
+    for (i, row) in observation_probability_matrix.rows().enumerate() {
+        validate_row(&format!("B row {}", i), row, number_of_observation_symbols)?;
+    }
+
+    Ok(())
+}
+
+fn validate_row(name: &str, row: &[f64], expected_length: usize) -> Result<(), HmmError> {
+    if row.len() != expected_length {
+        return Err(HmmError::ShapeMismatch(format!("{} has length {}, expected {}", name, row.len(), expected_length)));
+    }
+    if row.iter().any(|&probability| !(0.0..=1.0).contains(&probability)) {
+        return Err(HmmError::NotRowStochastic(format!("{} has an entry outside [0, 1]", name)));
+    }
+
+    let row_sum = row.iter().sum::<f64>();
+    if (row_sum - 1.0).abs() > ROW_SUM_TOLERANCE {
+        return Err(HmmError::NotRowStochastic(format!("{} sums to {}", name, row_sum)));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use super::*;
+    use crate::test_support::{assert_close, assert_matrices_close, matrix, test_model};
+
+    #[test]
+    fn a_saved_model_loads_unchanged() {
+        let mut model = test_model();
+        model.log_probability = -123.25;
+        model.validation_log_probability = Some(f64::NEG_INFINITY);
+        model.iterations = 7;
+        model.number_of_training_observations = 40;
+        model.seed = Some(99);
+        let path = env::temp_dir().join(format!("hmm_model_file_round_trip_{}.json", std::process::id()));
+
+        model.save(&path, "abc").unwrap();
+        let saved_model = HmmModel::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(saved_model.symbols, "abc");
+        assert_matrices_close(&saved_model.model.state_transition_matrix, &model.state_transition_matrix, 0.0);
+        assert_matrices_close(&saved_model.model.observation_probability_matrix, &model.observation_probability_matrix, 0.0);
+        assert_close(&saved_model.model.initial_state_distribution_vector, &model.initial_state_distribution_vector, 0.0);
+        assert_eq!(saved_model.model.log_probability, -123.25);
+        assert_eq!(saved_model.model.validation_log_probability, Some(f64::NEG_INFINITY));
+        assert_eq!((saved_model.model.iterations, saved_model.model.number_of_training_observations, saved_model.model.seed), (7, 40, Some(99)));
+    }
+
+    #[test]
+    fn an_untrained_model_survives_a_round_trip() {
+        // HmmModel::new leaves the log probability at -inf, which JSON can't hold as a number
+        let path = env::temp_dir().join(format!("hmm_model_file_untrained_{}.json", std::process::id()));
+
+        test_model().save(&path, "abc").unwrap();
+        let saved_model = HmmModel::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        let saved_model = saved_model.unwrap();
+        assert_eq!(saved_model.model.log_probability, f64::NEG_INFINITY);
+        assert_eq!(saved_model.model.validation_log_probability, None);
+    }
+
+    #[test]
+    fn a_model_that_isnt_row_stochastic_is_rejected() {
+        let mut model = test_model();
+        model.state_transition_matrix = matrix(&[&[0.7, 0.4], &[0.4, 0.6]]);
+        let path = env::temp_dir().join(format!("hmm_model_file_not_stochastic_{}.json", std::process::id()));
+
+        assert!(matches!(model.save(&path, "abc"), Err(HmmError::NotRowStochastic(_))));
+        assert!(matches!(test_model().save(&path, "ab"), Err(HmmError::ShapeMismatch(_))));
+    }
+}
@@ -0,0 +1,230 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use rand::Rng;
+
+use crate::{FrozenParameters, HmmError, HmmModel, ParameterBlock, TrainingConfig};
+
+/// Penalizes a model's log likelihood by its number of free parameters, so that adding states has to pay for itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InformationCriterion {
+    /// AIC = 2k - 2 ln L
+    Aic,
+    /// BIC = k ln T - 2 ln L, which penalizes extra states more than AIC once T > 7
+    #[default]
+    Bic,
+}
+
+/// The best of the restarts for one number of hidden states, scored by both criteria.
+#[derive(Debug, Clone, Copy)]
+pub struct ModelSelectionCandidate {
+    pub number_of_hidden_state_symbols: usize,
+    /// log2 P(O | model), as reported by training.
+    pub log_probability: f64,
+    pub number_of_free_parameters: usize,
+    pub aic: f64,
+    pub bic: f64,
+}
+
+impl ModelSelectionCandidate {
+    pub fn get_criterion(&self, criterion: InformationCriterion) -> f64 {
+        match criterion {
+            InformationCriterion::Aic => self.aic,
+            InformationCriterion::Bic => self.bic,
+        }
+    }
+}
+
+/// Every number of hidden states tried by `HmmModel::select_number_of_hidden_states`, and the model it picked.
+///
+/// Displays as a table with one row per candidate, the selected one marked with `*`.
+#[derive(Debug, Clone)]
+pub struct ModelSelection {
+    /// In increasing number of hidden states. Numbers of states whose every restart failed are left out.
+    pub candidates: Box<[ModelSelectionCandidate]>,
+    pub criterion: InformationCriterion,
+    /// The trained model of the candidate with the lowest criterion.
+    pub best_model: HmmModel,
+}
+
+impl HmmModel {
+    /// Trains `number_of_restarts` restarts for every number of hidden states in `numbers_of_hidden_state_symbols`
+    /// and keeps the model with the lowest `criterion`.
+    ///
+    /// `train_restart` is called with a number of hidden states and the restart's config, as in
+    /// `train_best_of_restarts`. Every number of states uses the same base seed, so a run can be replayed with
+    /// `--seed`. Ties go to the fewest states. A number of states whose every restart fails is skipped; if all
+    /// of them fail, the error of the smallest one is returned.
+    pub fn select_number_of_hidden_states<F>(
+        numbers_of_hidden_state_symbols: RangeInclusive<usize>,
+        number_of_restarts: usize,
+        config: &TrainingConfig,
+        criterion: InformationCriterion,
+        train_restart: F,
+    ) -> Result<ModelSelection, HmmError>
+    where
+        F: Fn(usize, &TrainingConfig) -> Result<HmmModel, HmmError> + Sync,
+    {
+        if numbers_of_hidden_state_symbols.is_empty() {
+            return Err(HmmError::InvalidConfiguration(format!(
+                "no numbers of hidden states between {} and {}",
+                numbers_of_hidden_state_symbols.start(),
+                numbers_of_hidden_state_symbols.end()
+            )));
+        }
+        let config = config.clone().seed(config.seed.unwrap_or_else(|| rand::thread_rng().gen()));
+
+        let mut candidates = Vec::new();
+        let mut best_model: Option<HmmModel> = None;
+        let mut first_error = None;
+        for number_of_hidden_state_symbols in numbers_of_hidden_state_symbols {
+            let model = match HmmModel::train_best_of_restarts(number_of_restarts, &config, |restart_training_config| {
+                train_restart(number_of_hidden_state_symbols, restart_training_config)
+            }) {
+                Err(error) => {
+                    first_error.get_or_insert(error);
+                    continue;
+                }
+                Ok(model) => model,
+            };
+
+            let candidate = model.score_for_selection(&config.frozen_parameters);
+            let is_best = match candidates.iter().map(|candidate: &ModelSelectionCandidate| candidate.get_criterion(criterion)).reduce(f64::min) {
+                None => true,
+                Some(best_criterion) => candidate.get_criterion(criterion) < best_criterion,
+            };
+            if is_best {
+                best_model = Some(model);
+            }
+            candidates.push(candidate);
+        }
+
+        match best_model {
+            None => Err(first_error.expect("a number of hidden states was tried")),
+            Some(best_model) => Ok(ModelSelection {
+                candidates: candidates.into_boxed_slice(),
+                criterion,
+                best_model,
+            }),
+        }
+    }
+
+    /// Number of probabilities training could choose freely: each row of A, B and pi sums to 1, so it has one
+    /// fewer free parameter than entries. Frozen blocks don't count.
+    pub fn get_number_of_free_parameters(&self, frozen_parameters: &FrozenParameters) -> usize {
+        let number_of_hidden_state_symbols = self.get_number_of_hidden_state_symbols();
+        let number_of_observation_symbols = self.get_number_of_observation_symbols();
+
+        let mut number_of_free_parameters = 0;
+        if !frozen_parameters.is_frozen(ParameterBlock::StateTransitionMatrix) {
+            number_of_free_parameters += number_of_hidden_state_symbols * (number_of_hidden_state_symbols - 1);
+        }
+        if !frozen_parameters.is_frozen(ParameterBlock::ObservationProbabilityMatrix) {
+            number_of_free_parameters += number_of_hidden_state_symbols * (number_of_observation_symbols - 1);
+        }
+        if !frozen_parameters.is_frozen(ParameterBlock::InitialStateDistributionVector) {
+            number_of_free_parameters += number_of_hidden_state_symbols - 1;
+        }
+
+        number_of_free_parameters
+    }
+
+    fn score_for_selection(&self, frozen_parameters: &FrozenParameters) -> ModelSelectionCandidate {
+        let number_of_free_parameters = self.get_number_of_free_parameters(frozen_parameters) as f64;
+        // The criteria use the natural log: ln L = log2 L * ln 2
+        let natural_log_likelihood = self.log_probability * std::f64::consts::LN_2;
+
+        ModelSelectionCandidate {
+            number_of_hidden_state_symbols: self.get_number_of_hidden_state_symbols(),
+            log_probability: self.log_probability,
+            number_of_free_parameters: number_of_free_parameters as usize,
+            aic: 2.0 * number_of_free_parameters - 2.0 * natural_log_likelihood,
+            bic: number_of_free_parameters * (self.number_of_training_observations as f64).ln() - 2.0 * natural_log_likelihood,
+        }
+    }
+}
+
+impl fmt::Display for ModelSelection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{:>8} {:>16} {:>11} {:>14} {:>14}", "states", "log2 likelihood", "parameters", "AIC", "BIC")?;
+        let best_number_of_hidden_state_symbols = self.best_model.get_number_of_hidden_state_symbols();
+        for candidate in self.candidates.iter() {
+            writeln!(
+                f,
+                "{:>8} {:>16.2} {:>11} {:>14.2} {:>14.2}{}",
+                candidate.number_of_hidden_state_symbols,
+                candidate.log_probability,
+                candidate.number_of_free_parameters,
+                candidate.aic,
+                candidate.bic,
+                if candidate.number_of_hidden_state_symbols == best_number_of_hidden_state_symbols { " *" } else { "" }
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{assert_close, test_model};
+    use crate::Matrix;
+
+    /// A model with `number_of_hidden_state_symbols` states and 3 symbols, as if trained on 100 observations.
+    fn trained_model(number_of_hidden_state_symbols: usize, log_probability: f64) -> HmmModel {
+        let n = number_of_hidden_state_symbols;
+        let mut model = HmmModel::new(Matrix::new(n, n, 1.0 / n as f64), Matrix::new(n, 3, 1.0 / 3.0), vec![1.0 / n as f64; n].into_boxed_slice());
+        model.log_probability = log_probability;
+        model.number_of_training_observations = 100;
+        model
+    }
+
+    fn select(criterion: InformationCriterion, log_probabilities: [f64; 3]) -> ModelSelection {
+        let config = TrainingConfig::default().seed(1).print_progress(false);
+        HmmModel::select_number_of_hidden_states(1..=3, 2, &config, criterion, |n, _| Ok(trained_model(n, log_probabilities[n - 1]))).unwrap()
+    }
+
+    #[test]
+    fn free_parameters_leave_out_frozen_blocks() {
+        let model = test_model();
+
+        assert_eq!(model.get_number_of_free_parameters(&FrozenParameters::default()), 2 + 4 + 1);
+        assert_eq!(model.get_number_of_free_parameters(&FrozenParameters::default().state_transition_matrix(model.state_transition_matrix.clone())), 4 + 1);
+    }
+
+    #[test]
+    fn the_lowest_criterion_is_selected() {
+        // More states fit better, and 3 is worth its parameters to AIC but not to BIC
+        let log_probabilities = [-200.0, -180.0, -165.0];
+
+        let selection = select(InformationCriterion::Aic, log_probabilities);
+        let aic = selection.candidates.iter().map(|candidate| candidate.aic).collect::<Vec<_>>();
+        let bic = selection.candidates.iter().map(|candidate| candidate.bic).collect::<Vec<_>>();
+        let natural_log_likelihoods = log_probabilities.iter().map(|log_probability| log_probability * std::f64::consts::LN_2).collect::<Vec<_>>();
+        assert_eq!(selection.candidates.iter().map(|candidate| candidate.number_of_free_parameters).collect::<Vec<_>>(), vec![2, 7, 14]);
+        assert_close(&aic, &[4.0 - 2.0 * natural_log_likelihoods[0], 14.0 - 2.0 * natural_log_likelihoods[1], 28.0 - 2.0 * natural_log_likelihoods[2]], 1e-9);
+        assert_close(&bic, &[2.0 * 100f64.ln() - 2.0 * natural_log_likelihoods[0], 7.0 * 100f64.ln() - 2.0 * natural_log_likelihoods[1], 14.0 * 100f64.ln() - 2.0 * natural_log_likelihoods[2]], 1e-9);
+        assert_eq!(selection.best_model.get_number_of_hidden_state_symbols(), 3);
+
+        assert_eq!(select(InformationCriterion::Bic, log_probabilities).best_model.get_number_of_hidden_state_symbols(), 2);
+    }
+
+    #[test]
+    fn ties_go_to_the_fewest_states_and_failures_are_skipped() {
+        let config = TrainingConfig::default().seed(1).print_progress(false);
+        // 2 and 3 states tie on AIC: 3 states have 7 more parameters and 7 / ln 2 more bits of log likelihood
+        let log_probabilities = [f64::NAN, -180.0, -180.0 + 7.0 / std::f64::consts::LN_2];
+
+        let selection = HmmModel::select_number_of_hidden_states(1..=3, 1, &config, InformationCriterion::Aic, |n, _| {
+            if n == 1 {
+                return Err(HmmError::ImpossibleObservations);
+            }
+            Ok(trained_model(n, log_probabilities[n - 1]))
+        })
+        .unwrap();
+
+        assert_eq!(selection.candidates.iter().map(|candidate| candidate.number_of_hidden_state_symbols).collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(selection.best_model.get_number_of_hidden_state_symbols(), 2);
+    }
+}
@@ -0,0 +1,60 @@
+use std::fmt::Debug;
+
+use crate::HmmError;
+
+/// What training should do after an iteration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrainingControl {
+    Continue,
+    /// Stop now and keep the parameters from the iteration just finished.
+    Stop,
+}
+
+/// Receives training events, e.g. to print progress, log to a file or cancel a run.
+///
+/// Set with `TrainingConfig::observer`. Restarts train in parallel and share their config's observer, so its
+/// methods may be called from several threads at once and the iterations of different restarts interleave.
+/// Every method does nothing by default.
+pub trait TrainingObserver: Debug + Send + Sync {
+    /// Called after every Baum-Welch iteration with log2 P(O | model) under the parameters it started from.
+    fn iteration_finished(&self, _iteration: usize, _log_probability: f64) -> TrainingControl {
+        TrainingControl::Continue
+    }
+
+    /// Called after every iteration but the first when training has a validation sequence, with its log2
+    /// probability under the same parameters as the training log probability passed to `iteration_finished`.
+    fn validation_scored(&self, _iteration: usize, _log_probability: f64) {}
+
+    /// Called once training stops. `converged` is whether the last improvement was within the improvement threshold,
+    /// rather than training running out of iterations or being stopped.
+    fn training_finished(&self, _iterations: usize, _log_probability: f64, _converged: bool) {}
+
+    /// Called by `train_best_of_restarts` of every kind of model as each restart finishes, with its index and the
+    /// trained model's log2 P(O | model), or why training failed.
+    fn restart_finished(&self, _restart: usize, _log_probability: Result<f64, &HmmError>) {}
+}
+
+/// Ignores every event.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SilentObserver;
+
+impl TrainingObserver for SilentObserver {}
+
+/// Prints the log probability after every iteration, and a line when training is done.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProgressPrinter;
+
+impl TrainingObserver for ProgressPrinter {
+    fn iteration_finished(&self, iteration: usize, log_probability: f64) -> TrainingControl {
+        println!("Finished iteration {:?}. New log probability: {:.*}", iteration, 2, log_probability);
+        TrainingControl::Continue
+    }
+
+    fn validation_scored(&self, iteration: usize, log_probability: f64) {
+        println!("Iteration {:?}. Validation log probability: {:.*}", iteration, 2, log_probability);
+    }
+
+    fn training_finished(&self, _iterations: usize, _log_probability: f64, _converged: bool) {
+        println!("Done training.");
+    }
+}
@@ -0,0 +1,49 @@
+use crate::forward_backward::{ForwardBackward, ForwardBackwardArithmetic};
+use crate::{HmmError, HmmModel, Matrix};
+
+impl HmmModel {
+    /// Runs forward-backward under the current parameters and returns gamma, where row t holds
+    /// P(X_t = i | O, model) for every hidden state i. Every row is 0 if the observations are impossible.
+    pub fn posterior_state_probabilities(&self, observations: &[usize]) -> Result<Matrix, HmmError> {
+        let emission_probability_matrix = self.emission_probability_matrix(observations)?;
+        if observations.is_empty() {
+            return Ok(Matrix::new(0, self.get_number_of_hidden_state_symbols(), 0.0));
+        }
+
+        let mut forward_backward = ForwardBackward::new(self.get_number_of_hidden_state_symbols(), observations.len(), ForwardBackwardArithmetic::ScaleFactors);
+        forward_backward.compute(&self.state_transition_matrix, &self.initial_state_distribution_vector, &emission_probability_matrix, None);
+        // A scale factor of 1 / 0 means alpha vanished, either by underflow or because the sequence is impossible.
+        // Log space tells the two apart, and gives an impossible sequence a posterior of all zeros.
+        if !forward_backward.log_probability().is_finite() {
+            forward_backward = ForwardBackward::new(self.get_number_of_hidden_state_symbols(), observations.len(), ForwardBackwardArithmetic::LogSpace);
+            forward_backward.compute(&self.state_transition_matrix, &self.initial_state_distribution_vector, &emission_probability_matrix, None);
+        }
+
+        Ok(forward_backward.gamma_matrix)
+    }
+
+    /// Picks the individually most likely state at each position ("max marginal" decoding).
+    ///
+    /// Unlike `viterbi` this maximizes the expected number of correct states, so the path may contain
+    /// transitions that A gives zero probability. An impossible sequence has no posterior to decode, so it's an
+    /// error.
+    pub fn posterior_decode(&self, observations: &[usize]) -> Result<Box<[usize]>, HmmError> {
+        let gamma_matrix = self.posterior_state_probabilities(observations)?;
+        if gamma_matrix.rows().any(|gamma_vector| gamma_vector.iter().all(|&probability| probability == 0.0)) {
+            return Err(HmmError::ImpossibleObservations);
+        }
+
+        Ok(gamma_matrix
+            .rows()
+            .map(|gamma_vector| {
+                let mut best_state = 0;
+                for (i, &probability) in gamma_vector.iter().enumerate() {
+                    if probability > gamma_vector[best_state] {
+                        best_state = i;
+                    }
+                }
+                best_state
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,146 @@
+use rand::Rng;
+use rayon::prelude::*;
+
+use crate::{HmmError, HmmModel, SecondOrderHmmModel, TrainingConfig};
+
+impl HmmModel {
+    /// Trains `number_of_restarts` models on all CPU cores and keeps the one with the highest log probability.
+    ///
+    /// Restart i calls `train_restart` with `config` seeded with base_seed + i, where base_seed is `config`'s seed
+    /// (or a fresh one if it has none). The winner doesn't depend on thread scheduling: ties go to the lowest i,
+    /// exactly as if the restarts had run one after another.
+    ///
+    /// Restarts that fail are skipped; if every one fails, the error of the lowest i is returned. Each restart is
+    /// reported to `config`'s observer as it finishes.
+    pub fn train_best_of_restarts<F>(number_of_restarts: usize, config: &TrainingConfig, train_restart: F) -> Result<HmmModel, HmmError>
+    where
+        F: Fn(&TrainingConfig) -> Result<HmmModel, HmmError> + Sync,
+    {
+        best_of_restarts(number_of_restarts, config, train_restart, |model| model.log_probability)
+    }
+}
+
+impl SecondOrderHmmModel {
+    /// `HmmModel::train_best_of_restarts` for second-order models.
+    pub fn train_best_of_restarts<F>(number_of_restarts: usize, config: &TrainingConfig, train_restart: F) -> Result<SecondOrderHmmModel, HmmError>
+    where
+        F: Fn(&TrainingConfig) -> Result<SecondOrderHmmModel, HmmError> + Sync,
+    {
+        best_of_restarts(number_of_restarts, config, train_restart, |model| model.log_probability)
+    }
+}
+
+/// Runs the restarts in parallel and keeps the best by `log_probability`, reporting each one to `config`'s observer.
+fn best_of_restarts<M: Send>(
+    number_of_restarts: usize,
+    config: &TrainingConfig,
+    train_restart: impl Fn(&TrainingConfig) -> Result<M, HmmError> + Sync,
+    log_probability: impl Fn(&M) -> f64 + Sync,
+) -> Result<M, HmmError> {
+    if number_of_restarts == 0 {
+        return Err(HmmError::InvalidConfiguration("there must be at least one restart".to_string()));
+    }
+    let base_seed = config.seed.unwrap_or_else(|| rand::thread_rng().gen());
+
+    (0..number_of_restarts as u64)
+        .into_par_iter()
+        .map(|i| {
+            let result = train_restart(&config.clone().seed(base_seed.wrapping_add(i)));
+            config.observer.restart_finished(i as usize, result.as_ref().map(&log_probability));
+            result
+        })
+        // reduce_with only combines neighbours, so keeping the left result on ties keeps the lowest i
+        .reduce_with(|best_result, result| match (best_result, result) {
+            (Ok(best_model), Ok(model)) => {
+                if comparable_log_probability(log_probability(&model)) > comparable_log_probability(log_probability(&best_model)) {
+                    Ok(model)
+                } else {
+                    Ok(best_model)
+                }
+            }
+            (Err(_), Ok(model)) => Ok(model),
+            (best_result, _) => best_result,
+        })
+        .expect("there is at least one restart")
+}
+
+/// Ranks a diverged (NaN) restart below every other one, so the comparison is a total order.
+fn comparable_log_probability(log_probability: f64) -> f64 {
+    if log_probability.is_nan() {
+        f64::NEG_INFINITY
+    } else {
+        log_probability
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::test_support::{pseudo_random_observations, test_model};
+    use crate::TrainingObserver;
+
+    /// Records the index and log probability of every restart.
+    #[derive(Debug, Default)]
+    struct RestartRecorder {
+        restarts: Mutex<Vec<(usize, f64)>>,
+    }
+
+    impl TrainingObserver for RestartRecorder {
+        fn restart_finished(&self, restart: usize, log_probability: Result<f64, &HmmError>) {
+            self.restarts.lock().unwrap().push((restart, log_probability.unwrap()));
+        }
+    }
+
+    #[test]
+    fn second_order_restarts_are_reported() {
+        let observations = [0, 1, 2, 2, 1, 0, 0, 1, 2, 1];
+        let recorder = Arc::new(RestartRecorder::default());
+        let config = TrainingConfig::default().seed(4).iterations(3).observer(recorder.clone());
+
+        let best_model = SecondOrderHmmModel::train_best_of_restarts(4, &config, |restart_training_config| SecondOrderHmmModel::train_model(2, 3, &observations, restart_training_config)).unwrap();
+
+        let mut restarts = recorder.restarts.lock().unwrap().clone();
+        restarts.sort_by_key(|&(restart, _)| restart);
+        assert_eq!(restarts.iter().map(|&(restart, _)| restart).collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+        assert_eq!(restarts.iter().map(|&(_, log_probability)| log_probability).fold(f64::NEG_INFINITY, f64::max), best_model.log_probability);
+    }
+
+    #[test]
+    fn the_best_restart_is_the_best_of_the_seeded_runs() {
+        let observations = pseudo_random_observations(100, 3, 1);
+        let config = TrainingConfig::default().seed(10).iterations(5).print_progress(false);
+        let train_restart = |restart_training_config: &TrainingConfig| HmmModel::train_model(2, 3, &observations, restart_training_config);
+
+        let best_model = HmmModel::train_best_of_restarts(6, &config, train_restart).unwrap();
+
+        let best_seeded_run = (10..16).map(|seed| train_restart(&config.clone().seed(seed)).unwrap()).max_by(|a, b| a.log_probability.partial_cmp(&b.log_probability).unwrap()).unwrap();
+        assert_eq!(best_model.log_probability, best_seeded_run.log_probability);
+        assert_eq!(best_model.seed, best_seeded_run.seed);
+        assert_eq!(HmmModel::train_best_of_restarts(6, &config, train_restart).unwrap().seed, best_model.seed);
+    }
+
+    #[test]
+    fn ties_and_failures_go_to_the_lowest_restart() {
+        let config = TrainingConfig::default().seed(10).print_progress(false);
+        // Restart i has seed 10 + i; the first two fail and the rest tie
+        let train_restart = |restart_training_config: &TrainingConfig| {
+            let seed = restart_training_config.get_seed().unwrap();
+            if seed < 12 {
+                return Err(HmmError::InvalidConfiguration(format!("restart with seed {}", seed)));
+            }
+            let mut model = test_model();
+            model.log_probability = -5.0;
+            model.seed = Some(seed);
+            Ok(model)
+        };
+
+        assert_eq!(HmmModel::train_best_of_restarts(8, &config, train_restart).unwrap().seed, Some(12));
+        match HmmModel::train_best_of_restarts(2, &config, train_restart) {
+            Err(HmmError::InvalidConfiguration(reason)) => assert_eq!(reason, "restart with seed 10"),
+            result => panic!("expected the first restart's error, got {:?}", result),
+        }
+        assert!(matches!(HmmModel::train_best_of_restarts(0, &config, train_restart), Err(HmmError::InvalidConfiguration(_))));
+    }
+}
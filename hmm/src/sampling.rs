@@ -0,0 +1,104 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::{HmmError, HmmModel};
+
+/// Hidden states and observations drawn from a model, so the true state path is known.
+#[derive(Debug, Clone)]
+pub struct SampledSequence {
+    pub state_path: Box<[usize]>,
+    pub observations: Box<[usize]>,
+}
+
+impl HmmModel {
+    /// Draws `length` hidden states from pi and A, emitting an observation from B at each one.
+    ///
+    /// Passing the same `seed` again gives the same sequence; `None` draws a fresh one. Fails if A, B and pi
+    /// don't fit together.
+    pub fn sample(&self, length: usize, seed: Option<u64>) -> Result<SampledSequence, HmmError> {
+        self.check_parameter_shapes()?;
+        let mut rng = StdRng::seed_from_u64(seed.unwrap_or_else(|| rand::thread_rng().gen()));
+        let mut state_path: Vec<usize> = Vec::with_capacity(length);
+        let mut observations = Vec::with_capacity(length);
+
+        for observation_index in 0..length {
+            let state = if observation_index == 0 {
+                sample_index(&self.initial_state_distribution_vector, &mut rng)
+            } else {
+                sample_index(&self.state_transition_matrix[state_path[observation_index - 1]], &mut rng)
+            };
+
+            state_path.push(state);
+            observations.push(sample_index(&self.observation_probability_matrix[state], &mut rng));
+        }
+
+        Ok(SampledSequence {
+            state_path: state_path.into_boxed_slice(),
+            observations: observations.into_boxed_slice(),
+        })
+    }
+}
+
+/// Picks an index with the given probabilities.
+fn sample_index(probabilities: &[f64], rng: &mut StdRng) -> usize {
+    let target = rng.gen::<f64>() * probabilities.iter().sum::<f64>();
+
+    let mut cumulative_probability = 0.0;
+    for (index, &probability) in probabilities.iter().enumerate() {
+        cumulative_probability += probability;
+        if target < cumulative_probability {
+            return index;
+        }
+    }
+
+    // Rounding can leave target just past the last boundary
+    probabilities.iter().rposition(|&probability| probability > 0.0).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{matrix, test_model};
+
+    #[test]
+    fn the_same_seed_gives_the_same_sequence() {
+        let model = test_model();
+
+        let sampled_sequence = model.sample(50, Some(9)).unwrap();
+        let resampled_sequence = model.sample(50, Some(9)).unwrap();
+
+        assert_eq!(sampled_sequence.state_path, resampled_sequence.state_path);
+        assert_eq!(sampled_sequence.observations, resampled_sequence.observations);
+    }
+
+    #[test]
+    fn sampled_frequencies_follow_the_model() {
+        let model = test_model();
+        let sampled_sequence = model.sample(100000, Some(3)).unwrap();
+
+        // Transitions out of state 0 and emissions from state 1, against their rows of A and B
+        let mut state_transition_counts = [0.0; 2];
+        for pair in sampled_sequence.state_path.windows(2).filter(|pair| pair[0] == 0) {
+            state_transition_counts[pair[1]] += 1.0;
+        }
+        let mut observation_counts = [0.0; 3];
+        for (_, &observation) in sampled_sequence.state_path.iter().zip(sampled_sequence.observations.iter()).filter(|&(&state, _)| state == 1) {
+            observation_counts[observation] += 1.0;
+        }
+
+        for (count, &probability) in state_transition_counts.iter().zip(model.state_transition_matrix[0].iter()) {
+            assert!((count / state_transition_counts.iter().sum::<f64>() - probability).abs() < 0.01);
+        }
+        for (count, &probability) in observation_counts.iter().zip(model.observation_probability_matrix[1].iter()) {
+            assert!((count / observation_counts.iter().sum::<f64>() - probability).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn mismatched_shapes_are_rejected() {
+        let mut model = test_model();
+        model.state_transition_matrix = matrix(&[&[0.7, 0.3]]);
+
+        assert!(matches!(model.sample(10, Some(1)), Err(HmmError::ShapeMismatch(_))));
+    }
+}
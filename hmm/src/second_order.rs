@@ -0,0 +1,836 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::error::check_symbols;
+use crate::expected_counts::{self, add_matrix, add_vector};
+use crate::forward_backward::{log2_matrix, log_sum_exp2};
+use crate::{baum_welch, ForwardBackwardArithmetic, HmmError, Matrix, ParameterBlock, SequenceScore, TrainingConfig, ViterbiPath};
+
+/// Hidden Markov model whose transitions depend on the two previous hidden states, with discrete emissions.
+///
+/// The first transition has only one previous state, so it comes from an ordinary N x N matrix.
+#[derive(Debug, Clone)]
+pub struct SecondOrderHmmModel {
+    /// Row i holds P(X_1 = j | X_0 = i) for every j.
+    pub first_state_transition_matrix: Matrix,
+    /// Row i * N + j holds P(X_t = k | X_t-2 = i, X_t-1 = j) for every k.
+    pub state_transition_tensor: Matrix,
+    pub observation_probability_matrix: Matrix,
+    pub initial_state_distribution_vector: Box<[f64]>,
+    /// log2 P(O | model) from the final training iteration, or with validation, under the returned parameters.
+    pub log_probability: f64,
+    /// log2 P(validation sequence | model), if training was given one.
+    pub validation_log_probability: Option<f64>,
+    /// Baum-Welch iterations run by training.
+    pub iterations: usize,
+    pub number_of_training_observations: usize,
+    /// Seed of the random initial guesses; training again with `TrainingConfig::seed` set to it gives the same model.
+    pub seed: Option<u64>,
+}
+
+impl SecondOrderHmmModel {
+    /// Creates an untrained model from known parameters, laid out as the fields describe.
+    pub fn new(first_state_transition_matrix: Matrix, state_transition_tensor: Matrix, observation_probability_matrix: Matrix, initial_state_distribution_vector: Box<[f64]>) -> SecondOrderHmmModel {
+        SecondOrderHmmModel {
+            first_state_transition_matrix,
+            state_transition_tensor,
+            observation_probability_matrix,
+            initial_state_distribution_vector,
+            log_probability: f64::NEG_INFINITY,
+            validation_log_probability: None,
+            iterations: 0,
+            number_of_training_observations: 0,
+            seed: None,
+        }
+    }
+
+    /// Trains the transitions, B and pi with Baum-Welch, starting from random guesses.
+    ///
+    /// A frozen A in `config` is used as the first transition matrix, and a frozen state transition tensor as the
+    /// second-order transitions. Everything else in `config` works as in `HmmModel::train_model`.
+    pub fn train_model(number_of_hidden_state_symbols: usize, number_of_observation_symbols: usize, observations: &[usize], config: &TrainingConfig) -> Result<SecondOrderHmmModel, HmmError> {
+        SecondOrderHmmModel::train_model_on_sequences(number_of_hidden_state_symbols, number_of_observation_symbols, &[observations], config)
+    }
+
+    /// Trains on several independent observation sequences. Empty sequences are skipped, and the E-step runs on
+    /// all CPU cores as in `HmmModel::train_model_on_sequences`.
+    pub fn train_model_on_sequences<S: AsRef<[usize]>>(number_of_hidden_state_symbols: usize, number_of_observation_symbols: usize, sequences: &[S], config: &TrainingConfig) -> Result<SecondOrderHmmModel, HmmError> {
+        if number_of_hidden_state_symbols == 0 || number_of_observation_symbols == 0 {
+            return Err(HmmError::InvalidConfiguration("a model needs at least one hidden state and one observation symbol".to_string()));
+        }
+        let frozen_parameters = &config.frozen_parameters;
+        frozen_parameters.check_shape(number_of_hidden_state_symbols, number_of_observation_symbols)?;
+        if let Some(state_transition_tensor) = &frozen_parameters.state_transition_tensor {
+            if state_transition_tensor.get_number_of_rows() != number_of_hidden_state_symbols * number_of_hidden_state_symbols || state_transition_tensor.get_number_of_columns() != number_of_hidden_state_symbols {
+                return Err(HmmError::ShapeMismatch(format!(
+                    "frozen state transition tensor is {}x{}, expected {}x{}",
+                    state_transition_tensor.get_number_of_rows(),
+                    state_transition_tensor.get_number_of_columns(),
+                    number_of_hidden_state_symbols * number_of_hidden_state_symbols,
+                    number_of_hidden_state_symbols
+                )));
+            }
+        }
+        let re_estimate_first_state_transition_matrix = !frozen_parameters.is_frozen(ParameterBlock::StateTransitionMatrix);
+        let re_estimate_state_transition_tensor = frozen_parameters.state_transition_tensor.is_none();
+        let re_estimate_observation_probability_matrix = !frozen_parameters.is_frozen(ParameterBlock::ObservationProbabilityMatrix);
+        let re_estimate_initial_state_distribution_vector = !frozen_parameters.is_frozen(ParameterBlock::InitialStateDistributionVector);
+        let seed = config.seed.unwrap_or_else(|| rand::thread_rng().gen());
+
+        let sequences: Box<[&[usize]]> = sequences.iter().map(|sequence| sequence.as_ref()).filter(|sequence| !sequence.is_empty()).collect();
+        if sequences.is_empty() {
+            return Err(HmmError::EmptySequence);
+        }
+        for sequence in sequences.iter() {
+            check_symbols(sequence, number_of_observation_symbols)?;
+        }
+        if let Some(validation_sequence) = &config.validation_sequence {
+            check_symbols(validation_sequence, number_of_observation_symbols)?;
+        }
+        if config.validation_observations.is_some() {
+            return Err(HmmError::InvalidConfiguration("validation observations are real-valued, which needs a GaussianHmmModel".to_string()));
+        }
+
+        let number_of_state_pairs = number_of_hidden_state_symbols * number_of_hidden_state_symbols;
+        let mut model = SecondOrderHmmModel::new(
+            frozen_parameters.state_transition_matrix.clone().unwrap_or_else(|| Matrix::new(number_of_hidden_state_symbols, number_of_hidden_state_symbols, 0.0)),
+            frozen_parameters.state_transition_tensor.clone().unwrap_or_else(|| Matrix::new(number_of_state_pairs, number_of_hidden_state_symbols, 0.0)),
+            frozen_parameters.observation_probability_matrix.clone().unwrap_or_else(|| Matrix::new(number_of_hidden_state_symbols, number_of_observation_symbols, 0.0)),
+            frozen_parameters.initial_state_distribution_vector.clone().unwrap_or_else(|| vec![0.0; number_of_hidden_state_symbols].into_boxed_slice()),
+        );
+        model.number_of_training_observations = sequences.iter().map(|sequence| sequence.len()).sum();
+        model.seed = Some(seed);
+        let mut sequence_chunks = expected_counts::chunk_sequences(
+            &sequences,
+            |sequence| sequence.len(),
+            |sequence| SecondOrderForwardBackward::new(number_of_hidden_state_symbols, sequence.len(), config.forward_backward_arithmetic),
+        );
+
+        // Generate guesses
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut guess_row = |row: &mut [f64]| {
+            baum_welch::draw_initial_values(row, &mut rng, config);
+            baum_welch::normalize(row);
+        };
+        if re_estimate_first_state_transition_matrix {
+            model.first_state_transition_matrix.rows_mut().for_each(&mut guess_row);
+        }
+        if re_estimate_state_transition_tensor {
+            model.state_transition_tensor.rows_mut().for_each(&mut guess_row);
+        }
+        if re_estimate_observation_probability_matrix {
+            model.observation_probability_matrix.rows_mut().for_each(&mut guess_row);
+        }
+        if re_estimate_initial_state_distribution_vector {
+            guess_row(&mut model.initial_state_distribution_vector);
+        }
+
+        let run = baum_welch::run_baum_welch(
+            model,
+            config,
+            |model| {
+                let expected_counts = expected_counts::sum_over_chunks(
+                    &mut sequence_chunks,
+                    || SecondOrderExpectedCounts::new(number_of_hidden_state_symbols, number_of_observation_symbols),
+                    |expected_counts, forward_backward, observations| expected_counts.add_sequence(forward_backward, model, observations, config.exclude_final_emission),
+                    SecondOrderExpectedCounts::add,
+                );
+                let log_probability = expected_counts.log_probability;
+                (expected_counts, log_probability)
+            },
+            |model| match &config.validation_sequence {
+                None => Ok(None),
+                Some(validation_sequence) => Ok(Some(model.evaluate_with_arithmetic(validation_sequence, config.forward_backward_arithmetic)?.log_probability)),
+            },
+            |model, expected_counts, iteration| {
+                if re_estimate_initial_state_distribution_vector {
+                    baum_welch::re_estimate_initial_state_distribution_vector(
+                        &mut model.initial_state_distribution_vector,
+                        &expected_counts.initial_state_counts,
+                        expected_counts.number_of_possible_sequences,
+                        config.initial_state_pseudocount,
+                    )?;
+                }
+                if re_estimate_first_state_transition_matrix {
+                    let first_state_transition_totals: Box<[f64]> = expected_counts.first_state_transition_counts.rows().map(|counts| counts.iter().sum::<f64>()).collect();
+                    baum_welch::re_estimate_rows(
+                        &mut model.first_state_transition_matrix,
+                        &expected_counts.first_state_transition_counts,
+                        &first_state_transition_totals,
+                        config.state_transition_pseudocount,
+                        "first transitions out of",
+                        iteration,
+                    )?;
+                }
+                if re_estimate_observation_probability_matrix {
+                    let observation_totals: Box<[f64]> = expected_counts.observation_counts.rows().map(|counts| counts.iter().sum::<f64>()).collect();
+                    baum_welch::re_estimate_rows(
+                        &mut model.observation_probability_matrix,
+                        &expected_counts.observation_counts,
+                        &observation_totals,
+                        config.observation_pseudocount,
+                        "emissions from",
+                        iteration,
+                    )?;
+                }
+
+                if re_estimate_state_transition_tensor {
+                    for (pair, counts) in expected_counts.state_transition_tensor_counts.rows().enumerate() {
+                        // a_ijk = (expected transitions i, j -> k + pseudocount) / (expected transitions out of i, j + N * pseudocount)
+                        let denominator = counts.iter().sum::<f64>() + number_of_hidden_state_symbols as f64 * config.state_transition_pseudocount;
+                        // With N^2 rows some pairs are never seen in a short sequence, so they keep their old row instead of failing
+                        if denominator <= 0.0 {
+                            continue;
+                        }
+                        for (probability, &count) in model.state_transition_tensor[pair].iter_mut().zip(counts.iter()) {
+                            *probability = (count + config.state_transition_pseudocount) / denominator;
+                        }
+                    }
+                }
+
+                Ok(())
+            },
+        )?;
+
+        let mut model = run.model;
+        model.log_probability = run.log_probability;
+        model.validation_log_probability = run.validation_log_probability;
+        model.iterations = run.iterations;
+
+        Ok(model)
+    }
+
+    /// Scores `observations` with the scaled second-order forward pass, scoring them again in log space if alpha
+    /// vanishes.
+    pub fn evaluate(&self, observations: &[usize]) -> Result<SequenceScore, HmmError> {
+        self.evaluate_with_arithmetic(observations, ForwardBackwardArithmetic::ScaleFactors)
+    }
+
+    /// `evaluate` with forward-backward in log space from the start, or with scale factors.
+    pub fn evaluate_with_arithmetic(&self, observations: &[usize], arithmetic: ForwardBackwardArithmetic) -> Result<SequenceScore, HmmError> {
+        self.check_parameter_shapes()?;
+        check_symbols(observations, self.get_number_of_observation_symbols())?;
+        if observations.is_empty() {
+            return Ok(SequenceScore::new(0.0, 0));
+        }
+
+        let forward_backward = self.run_forward_backward(observations, arithmetic);
+        Ok(SequenceScore::new(forward_backward.log_probability, observations.len()))
+    }
+
+    /// Finds the most likely hidden state sequence for `observations` with the second-order Viterbi algorithm,
+    /// which keeps the best path into every pair of states.
+    ///
+    /// Works with log2 probabilities so long sequences don't underflow. An impossible sequence is an error, as in
+    /// `HmmModel::viterbi`.
+    pub fn viterbi(&self, observations: &[usize]) -> Result<ViterbiPath, HmmError> {
+        self.check_parameter_shapes()?;
+        check_symbols(observations, self.get_number_of_observation_symbols())?;
+        let number_of_hidden_state_symbols = self.get_number_of_hidden_state_symbols();
+        let number_of_observations = observations.len();
+        if number_of_observations == 0 {
+            return Ok(ViterbiPath {
+                state_path: Vec::new().into_boxed_slice(),
+                log_probability: 0.0,
+            });
+        }
+
+        let log_observation_probability_matrix = log2_matrix(&self.observation_probability_matrix.transpose());
+
+        // delta_0(j) = log(pi_j * b_j(O_0))
+        let initial_delta_vector: Box<[f64]> = (0..number_of_hidden_state_symbols)
+            .map(|j| self.initial_state_distribution_vector[j].log2() + log_observation_probability_matrix[observations[0]][j])
+            .collect();
+        if number_of_observations == 1 {
+            let mut final_state = 0;
+            for (j, &delta) in initial_delta_vector.iter().enumerate() {
+                if delta > initial_delta_vector[final_state] {
+                    final_state = j;
+                }
+            }
+            if initial_delta_vector[final_state] == f64::NEG_INFINITY {
+                return Err(HmmError::ImpossibleObservations);
+            }
+            return Ok(ViterbiPath {
+                state_path: vec![final_state].into_boxed_slice(),
+                log_probability: initial_delta_vector[final_state],
+            });
+        }
+
+        // delta_1(j, k) = delta_0(j) + log(a_jk * b_k(O_1)), indexed j * N + k
+        let mut delta_vector = vec![f64::NEG_INFINITY; number_of_hidden_state_symbols * number_of_hidden_state_symbols].into_boxed_slice();
+        for j in 0..number_of_hidden_state_symbols {
+            for k in 0..number_of_hidden_state_symbols {
+                delta_vector[j * number_of_hidden_state_symbols + k] =
+                    initial_delta_vector[j] + self.first_state_transition_matrix[j][k].log2() + log_observation_probability_matrix[observations[1]][k];
+            }
+        }
+
+        let log_state_transition_tensor = log2_matrix(&self.state_transition_tensor);
+        // Row t holds the best X_t-2 for every pair (X_t-1, X_t), from t = 2 on
+        let mut back_pointer_matrix = vec![vec![0; delta_vector.len()].into_boxed_slice(); number_of_observations].into_boxed_slice();
+        for observation_index in 2..number_of_observations {
+            let log_observation_probability_vector = &log_observation_probability_matrix[observations[observation_index]];
+            let mut new_delta_vector = vec![f64::NEG_INFINITY; delta_vector.len()].into_boxed_slice();
+            for i in 0..number_of_hidden_state_symbols {
+                for j in 0..number_of_hidden_state_symbols {
+                    let previous_delta = delta_vector[i * number_of_hidden_state_symbols + j];
+                    if previous_delta == f64::NEG_INFINITY {
+                        continue;
+                    }
+
+                    // delta_t(j, k) = max_i(delta_t-1(i, j) + log(a_ijk)) + log(b_k(O_t))
+                    for (k, &log_state_transition_probability) in log_state_transition_tensor[i * number_of_hidden_state_symbols + j].iter().enumerate() {
+                        let log_probability = previous_delta + log_state_transition_probability + log_observation_probability_vector[k];
+                        if log_probability > new_delta_vector[j * number_of_hidden_state_symbols + k] {
+                            new_delta_vector[j * number_of_hidden_state_symbols + k] = log_probability;
+                            back_pointer_matrix[observation_index][j * number_of_hidden_state_symbols + k] = i;
+                        }
+                    }
+                }
+            }
+
+            delta_vector = new_delta_vector;
+        }
+
+        let mut final_pair = 0;
+        for (pair, &delta) in delta_vector.iter().enumerate() {
+            if delta > delta_vector[final_pair] {
+                final_pair = pair;
+            }
+        }
+        if delta_vector[final_pair] == f64::NEG_INFINITY {
+            return Err(HmmError::ImpossibleObservations);
+        }
+
+        // Follow the back pointers from the final pair to 0
+        let mut state_path = vec![0; number_of_observations].into_boxed_slice();
+        state_path[number_of_observations - 2] = final_pair / number_of_hidden_state_symbols;
+        state_path[number_of_observations - 1] = final_pair % number_of_hidden_state_symbols;
+        for observation_index in (2..number_of_observations).rev() {
+            state_path[observation_index - 2] = back_pointer_matrix[observation_index][state_path[observation_index - 1] * number_of_hidden_state_symbols + state_path[observation_index]];
+        }
+
+        Ok(ViterbiPath {
+            state_path,
+            log_probability: delta_vector[final_pair],
+        })
+    }
+
+    /// Runs second-order forward-backward under the current parameters and returns gamma, where row t holds
+    /// P(X_t = i | O, model) for every hidden state i. Every row is 0 if the observations are impossible.
+    pub fn posterior_state_probabilities(&self, observations: &[usize]) -> Result<Matrix, HmmError> {
+        self.check_parameter_shapes()?;
+        check_symbols(observations, self.get_number_of_observation_symbols())?;
+        if observations.is_empty() {
+            return Ok(Matrix::new(0, self.get_number_of_hidden_state_symbols(), 0.0));
+        }
+
+        Ok(self.run_forward_backward(observations, ForwardBackwardArithmetic::ScaleFactors).gamma_matrix())
+    }
+
+    /// Forward-backward over non-empty `observations`, run again in log space if a scale factor is 1 / 0, which
+    /// tells underflow from an impossible sequence.
+    fn run_forward_backward(&self, observations: &[usize], arithmetic: ForwardBackwardArithmetic) -> SecondOrderForwardBackward {
+        let mut forward_backward = SecondOrderForwardBackward::new(self.get_number_of_hidden_state_symbols(), observations.len(), arithmetic);
+        forward_backward.compute(self, observations, None);
+        if !forward_backward.log_probability.is_finite() && arithmetic == ForwardBackwardArithmetic::ScaleFactors {
+            forward_backward = SecondOrderForwardBackward::new(self.get_number_of_hidden_state_symbols(), observations.len(), ForwardBackwardArithmetic::LogSpace);
+            forward_backward.compute(self, observations, None);
+        }
+
+        forward_backward
+    }
+
+    /// Checks that the first transition matrix is N x N, the tensor N^2 x N and B has N rows, where N is the
+    /// length of pi, since `new` takes any matrices.
+    pub(crate) fn check_parameter_shapes(&self) -> Result<(), HmmError> {
+        let number_of_hidden_state_symbols = self.get_number_of_hidden_state_symbols();
+        if self.first_state_transition_matrix.get_number_of_rows() != number_of_hidden_state_symbols
+            || self.first_state_transition_matrix.get_number_of_columns() != number_of_hidden_state_symbols
+            || self.state_transition_tensor.get_number_of_rows() != number_of_hidden_state_symbols * number_of_hidden_state_symbols
+            || self.state_transition_tensor.get_number_of_columns() != number_of_hidden_state_symbols
+            || self.observation_probability_matrix.get_number_of_rows() != number_of_hidden_state_symbols
+        {
+            return Err(HmmError::ShapeMismatch(format!(
+                "the first transition matrix is {}x{}, the transition tensor {}x{} and B has {} rows, but pi has {} entries",
+                self.first_state_transition_matrix.get_number_of_rows(),
+                self.first_state_transition_matrix.get_number_of_columns(),
+                self.state_transition_tensor.get_number_of_rows(),
+                self.state_transition_tensor.get_number_of_columns(),
+                self.observation_probability_matrix.get_number_of_rows(),
+                number_of_hidden_state_symbols
+            )));
+        }
+
+        Ok(())
+    }
+
+    pub fn get_number_of_hidden_state_symbols(&self) -> usize {
+        self.initial_state_distribution_vector.len()
+    }
+
+    pub fn get_number_of_observation_symbols(&self) -> usize {
+        self.observation_probability_matrix.get_number_of_columns()
+    }
+}
+
+/// Expected counts from the E-step of a second-order model, summed over some of the training sequences.
+#[derive(Debug)]
+struct SecondOrderExpectedCounts {
+    initial_state_counts: Box<[f64]>,
+    first_state_transition_counts: Matrix,
+    state_transition_tensor_counts: Matrix,
+    observation_counts: Matrix,
+    /// Sequences with a probability above 0, the only ones gamma counts.
+    number_of_possible_sequences: usize,
+    /// log2 P(O | model) summed over the same sequences.
+    log_probability: f64,
+}
+
+impl SecondOrderExpectedCounts {
+    fn new(number_of_hidden_state_symbols: usize, number_of_observation_symbols: usize) -> SecondOrderExpectedCounts {
+        SecondOrderExpectedCounts {
+            initial_state_counts: vec![0.0; number_of_hidden_state_symbols].into_boxed_slice(),
+            first_state_transition_counts: Matrix::new(number_of_hidden_state_symbols, number_of_hidden_state_symbols, 0.0),
+            state_transition_tensor_counts: Matrix::new(number_of_hidden_state_symbols * number_of_hidden_state_symbols, number_of_hidden_state_symbols, 0.0),
+            observation_counts: Matrix::new(number_of_hidden_state_symbols, number_of_observation_symbols, 0.0),
+            number_of_possible_sequences: 0,
+            log_probability: 0.0,
+        }
+    }
+
+    fn add_sequence(&mut self, forward_backward: &mut SecondOrderForwardBackward, model: &SecondOrderHmmModel, observations: &[usize], exclude_final_emission: bool) {
+        let n = model.get_number_of_hidden_state_symbols();
+        forward_backward.compute(model, observations, Some(&mut self.state_transition_tensor_counts));
+        let gamma_matrix = forward_backward.gamma_matrix();
+
+        for (initial_state_count, &gamma_value) in self.initial_state_counts.iter_mut().zip(gamma_matrix[0].iter()) {
+            *initial_state_count += gamma_value;
+        }
+
+        // The pair at t = 1 is exactly the first transition
+        if observations.len() > 1 {
+            for pair in 0..n * n {
+                self.first_state_transition_counts[pair / n][pair % n] += forward_backward.pair_posterior(0, pair);
+            }
+        }
+
+        let number_of_emissions = if exclude_final_emission { observations.len() - 1 } else { observations.len() };
+        for (gamma_vector, &observation) in gamma_matrix.rows().zip(observations.iter()).take(number_of_emissions) {
+            for (i, &gamma_value) in gamma_vector.iter().enumerate() {
+                self.observation_counts[i][observation] += gamma_value;
+            }
+        }
+
+        if forward_backward.log_probability > f64::NEG_INFINITY {
+            self.number_of_possible_sequences += 1;
+        }
+        self.log_probability += forward_backward.log_probability;
+    }
+
+    fn add(&mut self, other: &SecondOrderExpectedCounts) {
+        add_vector(&mut self.initial_state_counts, &other.initial_state_counts);
+        add_matrix(&mut self.first_state_transition_counts, &other.first_state_transition_counts);
+        add_matrix(&mut self.state_transition_tensor_counts, &other.state_transition_tensor_counts);
+        add_matrix(&mut self.observation_counts, &other.observation_counts);
+        self.number_of_possible_sequences += other.number_of_possible_sequences;
+        self.log_probability += other.log_probability;
+    }
+}
+
+/// Forward and backward variables of a second-order model over one observation sequence.
+///
+/// From t = 1 on the forward and backward variables belong to pairs of states (X_t-1, X_t), stored in row t - 1
+/// of the pair matrices at index j * N + k. With scale factors every time step is scaled by c_t = 1 / sum(alpha_t),
+/// so that alpha_t * beta_t is the posterior of the pair. In log space the vectors and matrices hold log2 alpha
+/// and log2 beta, unscaled, as in `ForwardBackward`.
+#[derive(Debug)]
+struct SecondOrderForwardBackward {
+    number_of_hidden_state_symbols: usize,
+    arithmetic: ForwardBackwardArithmetic,
+    initial_alpha_vector: Box<[f64]>,
+    initial_beta_vector: Box<[f64]>,
+    pair_alpha_matrix: Matrix,
+    pair_beta_matrix: Matrix,
+    scale_factors: Box<[f64]>,
+    /// log2 P(O | model), once `compute` has run.
+    log_probability: f64,
+}
+
+impl SecondOrderForwardBackward {
+    fn new(number_of_hidden_state_symbols: usize, number_of_observations: usize, arithmetic: ForwardBackwardArithmetic) -> SecondOrderForwardBackward {
+        let number_of_state_pairs = number_of_hidden_state_symbols * number_of_hidden_state_symbols;
+        SecondOrderForwardBackward {
+            number_of_hidden_state_symbols,
+            arithmetic,
+            initial_alpha_vector: vec![0.0; number_of_hidden_state_symbols].into_boxed_slice(),
+            initial_beta_vector: vec![0.0; number_of_hidden_state_symbols].into_boxed_slice(),
+            pair_alpha_matrix: Matrix::new(number_of_observations.saturating_sub(1), number_of_state_pairs, 0.0),
+            pair_beta_matrix: Matrix::new(number_of_observations.saturating_sub(1), number_of_state_pairs, 0.0),
+            scale_factors: vec![0.0; number_of_observations].into_boxed_slice(),
+            log_probability: f64::NEG_INFINITY,
+        }
+    }
+
+    /// Runs the forward and backward passes over a non-empty `observations`, adding the expected number of
+    /// i, j -> k transitions to `state_transition_tensor_counts` if given.
+    fn compute(&mut self, model: &SecondOrderHmmModel, observations: &[usize], state_transition_tensor_counts: Option<&mut Matrix>) {
+        if self.arithmetic == ForwardBackwardArithmetic::LogSpace {
+            self.compute_in_log_space(model, observations, state_transition_tensor_counts);
+            return;
+        }
+
+        self.compute_with_scale_factors(model, observations, state_transition_tensor_counts);
+        // log P(O | model) = -sum(log c_t)
+        self.log_probability = -self.scale_factors.iter().map(|scale_factor| scale_factor.log2()).sum::<f64>();
+    }
+
+    fn compute_with_scale_factors(&mut self, model: &SecondOrderHmmModel, observations: &[usize], mut state_transition_tensor_counts: Option<&mut Matrix>) {
+        let n = self.number_of_hidden_state_symbols;
+        let number_of_observations = observations.len();
+        // Row O_t holds b_k(O_t) for every k
+        let transposed_observation_probability_matrix = model.observation_probability_matrix.transpose();
+
+        // alpha_0(j) = pi_j * b_j(O_0)
+        for (j, alpha_value) in self.initial_alpha_vector.iter_mut().enumerate() {
+            *alpha_value = model.initial_state_distribution_vector[j] * transposed_observation_probability_matrix[observations[0]][j];
+        }
+        self.scale_factors[0] = scale(&mut self.initial_alpha_vector);
+
+        let mut pair_alpha_vector = vec![0.0; n * n].into_boxed_slice();
+        for observation_index in 1..number_of_observations {
+            let observation_probability_vector = &transposed_observation_probability_matrix[observations[observation_index]];
+            pair_alpha_vector.iter_mut().for_each(|alpha_value| *alpha_value = 0.0);
+
+            if observation_index == 1 {
+                // alpha_1(j, k) = alpha_0(j) * a_jk * b_k(O_1)
+                for (j, &previous_alpha_value) in self.initial_alpha_vector.iter().enumerate() {
+                    for k in 0..n {
+                        pair_alpha_vector[j * n + k] = previous_alpha_value * model.first_state_transition_matrix[j][k];
+                    }
+                }
+            } else {
+                // alpha_t(j, k) = sum_i(alpha_t-1(i, j) * a_ijk) * b_k(O_t)
+                for (pair, &previous_alpha_value) in self.pair_alpha_matrix[observation_index - 2].iter().enumerate() {
+                    if previous_alpha_value == 0.0 {
+                        continue;
+                    }
+                    let j = pair % n;
+                    for (alpha_value, &state_transition_probability) in pair_alpha_vector[j * n..(j + 1) * n].iter_mut().zip(model.state_transition_tensor[pair].iter()) {
+                        *alpha_value += previous_alpha_value * state_transition_probability;
+                    }
+                }
+            }
+
+            for (pair, alpha_value) in pair_alpha_vector.iter_mut().enumerate() {
+                *alpha_value *= observation_probability_vector[pair % n];
+            }
+            self.scale_factors[observation_index] = scale(&mut pair_alpha_vector);
+            self.pair_alpha_matrix[observation_index - 1].copy_from_slice(&pair_alpha_vector);
+        }
+
+        if number_of_observations == 1 {
+            self.initial_beta_vector.iter_mut().for_each(|beta_value| *beta_value = 1.0);
+            return;
+        }
+
+        // beta_T-1(j, k) = 1
+        self.pair_beta_matrix[number_of_observations - 2].iter_mut().for_each(|beta_value| *beta_value = 1.0);
+        for observation_index in (1..number_of_observations - 1).rev() {
+            let next_scale_factor = self.scale_factors[observation_index + 1];
+            let observation_probability_vector = &transposed_observation_probability_matrix[observations[observation_index + 1]];
+            // b_k(O_t+1) * beta_t+1(j, k)
+            let weighted_next_beta_vector: Box<[f64]> = self.pair_beta_matrix[observation_index].iter().enumerate().map(|(pair, &beta_value)| observation_probability_vector[pair % n] * beta_value).collect();
+
+            for pair in 0..n * n {
+                let j = pair % n;
+                let state_transition_probability_vector = &model.state_transition_tensor[pair];
+                let next_pair_range = j * n..(j + 1) * n;
+
+                // beta_t(i, j) = c_t+1 * sum_k(a_ijk * b_k(O_t+1) * beta_t+1(j, k))
+                self.pair_beta_matrix[observation_index - 1][pair] = next_scale_factor
+                    * state_transition_probability_vector.iter().zip(weighted_next_beta_vector[next_pair_range.clone()].iter()).map(|(a, b)| a * b).sum::<f64>();
+
+                if let Some(state_transition_tensor_counts) = &mut state_transition_tensor_counts {
+                    // xi_t+1(i, j, k) = alpha_t(i, j) * a_ijk * b_k(O_t+1) * beta_t+1(j, k) * c_t+1
+                    let alpha_value = self.pair_alpha_matrix[observation_index - 1][pair] * next_scale_factor;
+                    if alpha_value == 0.0 {
+                        continue;
+                    }
+                    for ((count, &state_transition_probability), &weighted_next_beta_value) in
+                        state_transition_tensor_counts[pair].iter_mut().zip(state_transition_probability_vector.iter()).zip(weighted_next_beta_vector[next_pair_range].iter())
+                    {
+                        *count += alpha_value * state_transition_probability * weighted_next_beta_value;
+                    }
+                }
+            }
+        }
+
+        // beta_0(j) = c_1 * sum_k(a_jk * b_k(O_1) * beta_1(j, k))
+        let observation_probability_vector = &transposed_observation_probability_matrix[observations[1]];
+        let next_pair_beta_vector = &self.pair_beta_matrix[0];
+        for (j, beta_value) in self.initial_beta_vector.iter_mut().enumerate() {
+            *beta_value = self.scale_factors[1] * (0..n).map(|k| model.first_state_transition_matrix[j][k] * observation_probability_vector[k] * next_pair_beta_vector[j * n + k]).sum::<f64>();
+        }
+    }
+
+    fn compute_in_log_space(&mut self, model: &SecondOrderHmmModel, observations: &[usize], mut state_transition_tensor_counts: Option<&mut Matrix>) {
+        let n = self.number_of_hidden_state_symbols;
+        let number_of_observations = observations.len();
+        // Row O_t holds log b_k(O_t) for every k
+        let log_observation_probability_matrix = log2_matrix(&model.observation_probability_matrix.transpose());
+        let log_first_state_transition_matrix = log2_matrix(&model.first_state_transition_matrix);
+        let log_state_transition_tensor = log2_matrix(&model.state_transition_tensor);
+        let mut terms = vec![0.0; n].into_boxed_slice();
+
+        // log alpha_0(j) = log pi_j + log b_j(O_0)
+        for (j, alpha_value) in self.initial_alpha_vector.iter_mut().enumerate() {
+            *alpha_value = model.initial_state_distribution_vector[j].log2() + log_observation_probability_matrix[observations[0]][j];
+        }
+        for observation_index in 1..number_of_observations {
+            let log_observation_probability_vector = &log_observation_probability_matrix[observations[observation_index]];
+            for j in 0..n {
+                for k in 0..n {
+                    let log_alpha_value = if observation_index == 1 {
+                        // log alpha_1(j, k) = log alpha_0(j) + log a_jk + log b_k(O_1)
+                        self.initial_alpha_vector[j] + log_first_state_transition_matrix[j][k]
+                    } else {
+                        // log alpha_t(j, k) = log(sum_i(alpha_t-1(i, j) * a_ijk)) + log b_k(O_t)
+                        for (i, term) in terms.iter_mut().enumerate() {
+                            *term = self.pair_alpha_matrix[observation_index - 2][i * n + j] + log_state_transition_tensor[i * n + j][k];
+                        }
+                        log_sum_exp2(&terms)
+                    };
+                    self.pair_alpha_matrix[observation_index - 1][j * n + k] = log_alpha_value + log_observation_probability_vector[k];
+                }
+            }
+        }
+
+        if number_of_observations == 1 {
+            self.log_probability = log_sum_exp2(&self.initial_alpha_vector);
+            self.initial_beta_vector.fill(0.0);
+            return;
+        }
+        self.log_probability = log_sum_exp2(&self.pair_alpha_matrix[number_of_observations - 2]);
+        // An impossible sequence has no posterior, so it adds no transitions rather than NaN
+        if self.log_probability == f64::NEG_INFINITY {
+            state_transition_tensor_counts = None;
+        }
+
+        // log beta_T-1(j, k) = log 1
+        self.pair_beta_matrix[number_of_observations - 2].fill(0.0);
+        for observation_index in (1..number_of_observations - 1).rev() {
+            let log_observation_probability_vector = &log_observation_probability_matrix[observations[observation_index + 1]];
+            for pair in 0..n * n {
+                let j = pair % n;
+                // log(a_ijk * b_k(O_t+1) * beta_t+1(j, k))
+                for (k, term) in terms.iter_mut().enumerate() {
+                    *term = log_state_transition_tensor[pair][k] + log_observation_probability_vector[k] + self.pair_beta_matrix[observation_index][j * n + k];
+                }
+
+                // log beta_t(i, j) = log(sum_k(a_ijk * b_k(O_t+1) * beta_t+1(j, k)))
+                self.pair_beta_matrix[observation_index - 1][pair] = log_sum_exp2(&terms);
+
+                if let Some(state_transition_tensor_counts) = &mut state_transition_tensor_counts {
+                    // xi_t+1(i, j, k) = alpha_t(i, j) * a_ijk * b_k(O_t+1) * beta_t+1(j, k) / P(O | model)
+                    let log_alpha_value = self.pair_alpha_matrix[observation_index - 1][pair] - self.log_probability;
+                    for (count, &term) in state_transition_tensor_counts[pair].iter_mut().zip(terms.iter()) {
+                        *count += (log_alpha_value + term).exp2();
+                    }
+                }
+            }
+        }
+
+        // log beta_0(j) = log(sum_k(a_jk * b_k(O_1) * beta_1(j, k)))
+        let log_observation_probability_vector = &log_observation_probability_matrix[observations[1]];
+        for j in 0..n {
+            for (k, term) in terms.iter_mut().enumerate() {
+                *term = log_first_state_transition_matrix[j][k] + log_observation_probability_vector[k] + self.pair_beta_matrix[0][j * n + k];
+            }
+            self.initial_beta_vector[j] = log_sum_exp2(&terms);
+        }
+    }
+
+    /// P(X_0 = j | O, model).
+    fn initial_posterior(&self, j: usize) -> f64 {
+        self.posterior(self.initial_alpha_vector[j], self.initial_beta_vector[j])
+    }
+
+    /// P(X_t-1 = i, X_t = j | O, model) for `pair` = i * N + j, where `pair_index` = t - 1.
+    fn pair_posterior(&self, pair_index: usize, pair: usize) -> f64 {
+        self.posterior(self.pair_alpha_matrix[pair_index][pair], self.pair_beta_matrix[pair_index][pair])
+    }
+
+    fn posterior(&self, alpha_value: f64, beta_value: f64) -> f64 {
+        match self.arithmetic {
+            ForwardBackwardArithmetic::ScaleFactors => alpha_value * beta_value,
+            // An impossible sequence has no posterior
+            ForwardBackwardArithmetic::LogSpace if self.log_probability == f64::NEG_INFINITY => 0.0,
+            ForwardBackwardArithmetic::LogSpace => (alpha_value + beta_value - self.log_probability).exp2(),
+        }
+    }
+
+    /// Row t holds P(X_t = i | O, model), summing the pair posteriors over the earlier state from t = 1 on.
+    fn gamma_matrix(&self) -> Matrix {
+        let n = self.number_of_hidden_state_symbols;
+        let mut gamma_matrix = Matrix::new(self.scale_factors.len(), n, 0.0);
+        for (j, gamma_value) in gamma_matrix[0].iter_mut().enumerate() {
+            *gamma_value = self.initial_posterior(j);
+        }
+
+        for pair_index in 0..self.pair_alpha_matrix.get_number_of_rows() {
+            for pair in 0..n * n {
+                gamma_matrix[pair_index + 1][pair % n] += self.pair_posterior(pair_index, pair);
+            }
+        }
+
+        gamma_matrix
+    }
+}
+
+/// Divides `values` by their sum and returns the scale factor 1 / sum.
+fn scale(values: &mut [f64]) -> f64 {
+    let scale_factor = 1.0 / values.iter().sum::<f64>();
+    for value in values.iter_mut() {
+        *value *= scale_factor;
+    }
+
+    scale_factor
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{assert_matrices_close, every_state_path, matrix};
+    use crate::HmmModel;
+
+    fn test_model() -> SecondOrderHmmModel {
+        SecondOrderHmmModel::new(
+            matrix(&[&[0.7, 0.3], &[0.4, 0.6]]),
+            matrix(&[&[0.9, 0.1], &[0.2, 0.8], &[0.5, 0.5], &[0.3, 0.7]]),
+            matrix(&[&[0.5, 0.4, 0.1], &[0.1, 0.3, 0.6]]),
+            vec![0.6, 0.4].into_boxed_slice(),
+        )
+    }
+
+    /// P(O), gamma and the most likely state path, by summing P(X, O) over every hidden state sequence X.
+    fn enumerate(model: &SecondOrderHmmModel, observations: &[usize]) -> (f64, Matrix, Vec<usize>) {
+        let n = model.get_number_of_hidden_state_symbols();
+        let mut total_probability = 0.0;
+        let mut gamma_matrix = Matrix::new(observations.len(), n, 0.0);
+        let mut best_path = (0.0, Vec::new());
+
+        for state_path in every_state_path(n, observations.len()) {
+            let mut probability = model.initial_state_distribution_vector[state_path[0]] * model.observation_probability_matrix[state_path[0]][observations[0]];
+            for t in 1..observations.len() {
+                probability *= match t {
+                    1 => model.first_state_transition_matrix[state_path[0]][state_path[1]],
+                    _ => model.state_transition_tensor[state_path[t - 2] * n + state_path[t - 1]][state_path[t]],
+                };
+                probability *= model.observation_probability_matrix[state_path[t]][observations[t]];
+            }
+
+            total_probability += probability;
+            for (t, &state) in state_path.iter().enumerate() {
+                gamma_matrix[t][state] += probability;
+            }
+            if probability > best_path.0 {
+                best_path = (probability, state_path);
+            }
+        }
+
+        for gamma_vector in gamma_matrix.rows_mut() {
+            gamma_vector.iter_mut().for_each(|probability| *probability /= total_probability);
+        }
+        (total_probability.log2(), gamma_matrix, best_path.1)
+    }
+
+    #[test]
+    fn forward_backward_and_viterbi_match_enumeration() {
+        let model = test_model();
+        for observations in [&[1][..], &[0, 2], &[2, 1, 0, 0, 2], &[0, 0, 1, 2, 2, 1, 0]] {
+            let (log_probability, gamma_matrix, state_path) = enumerate(&model, observations);
+
+            for arithmetic in [ForwardBackwardArithmetic::ScaleFactors, ForwardBackwardArithmetic::LogSpace] {
+                let mut forward_backward = SecondOrderForwardBackward::new(2, observations.len(), arithmetic);
+                forward_backward.compute(&model, observations, None);
+
+                assert!((forward_backward.log_probability - log_probability).abs() < 1e-12);
+                assert_matrices_close(&forward_backward.gamma_matrix(), &gamma_matrix, 1e-12);
+            }
+
+            let viterbi_path = model.viterbi(observations).unwrap();
+            assert_eq!(&*viterbi_path.state_path, &state_path[..]);
+        }
+    }
+
+    #[test]
+    fn both_arithmetics_count_the_same_transitions() {
+        let model = test_model();
+        let observations: Box<[usize]> = (0..400).map(|t| (t * 7 + t / 3) % 5 % 3).collect();
+
+        let mut scaled_counts = Matrix::new(4, 2, 0.0);
+        SecondOrderForwardBackward::new(2, observations.len(), ForwardBackwardArithmetic::ScaleFactors).compute(&model, &observations, Some(&mut scaled_counts));
+        let mut log_space_counts = Matrix::new(4, 2, 0.0);
+        SecondOrderForwardBackward::new(2, observations.len(), ForwardBackwardArithmetic::LogSpace).compute(&model, &observations, Some(&mut log_space_counts));
+
+        // Every transition from t = 2 on is counted once
+        assert!((scaled_counts.rows().flatten().sum::<f64>() - 398.0).abs() < 1e-9);
+        assert_matrices_close(&log_space_counts, &scaled_counts, 1e-8);
+    }
+
+    #[test]
+    fn an_impossible_sequence_has_no_posterior() {
+        let mut model = test_model();
+        model.observation_probability_matrix = matrix(&[&[0.5, 0.5, 0.0], &[0.5, 0.5, 0.0]]);
+
+        assert_eq!(model.evaluate(&[0, 2, 1]).unwrap().log_probability, f64::NEG_INFINITY);
+        assert!(model.posterior_state_probabilities(&[0, 2, 1]).unwrap().rows().flatten().all(|&probability| probability == 0.0));
+        assert!(matches!(model.viterbi(&[0, 2, 1]), Err(HmmError::ImpossibleObservations)));
+    }
+
+    #[test]
+    fn a_mismatched_tensor_is_rejected() {
+        let mut model = test_model();
+        model.state_transition_tensor = matrix(&[&[0.9, 0.1], &[0.2, 0.8]]);
+
+        assert!(matches!(model.evaluate(&[0, 1, 2]), Err(HmmError::ShapeMismatch(_))));
+        assert!(matches!(model.viterbi(&[0, 1, 2]), Err(HmmError::ShapeMismatch(_))));
+        assert!(matches!(model.posterior_state_probabilities(&[0, 1, 2]), Err(HmmError::ShapeMismatch(_))));
+    }
+
+    #[test]
+    fn a_tensor_that_ignores_the_earlier_state_matches_a_first_order_model() {
+        let state_transition_matrix = matrix(&[&[0.8, 0.2], &[0.3, 0.7]]);
+        let observation_probability_matrix = matrix(&[&[0.5, 0.4, 0.1], &[0.1, 0.3, 0.6]]);
+        let first_order_model = HmmModel::new(state_transition_matrix.clone(), observation_probability_matrix.clone(), vec![0.6, 0.4].into_boxed_slice());
+        // Row i * N + j is row j of A for every i
+        let state_transition_tensor = matrix(&[&[0.8, 0.2], &[0.3, 0.7], &[0.8, 0.2], &[0.3, 0.7]]);
+        let second_order_model = SecondOrderHmmModel::new(state_transition_matrix, state_transition_tensor, observation_probability_matrix, vec![0.6, 0.4].into_boxed_slice());
+        let observations: Box<[usize]> = (0..500).map(|t| (t * t + t / 7) % 3).collect();
+
+        let first_order_log_probability = first_order_model.evaluate(&observations).unwrap().log_probability;
+        assert!((second_order_model.evaluate(&observations).unwrap().log_probability - first_order_log_probability).abs() < 1e-9);
+
+        assert_matrices_close(
+            &second_order_model.posterior_state_probabilities(&observations).unwrap(),
+            &first_order_model.posterior_state_probabilities(&observations).unwrap(),
+            1e-12,
+        );
+        assert_eq!(first_order_model.viterbi(&observations).unwrap().state_path, second_order_model.viterbi(&observations).unwrap().state_path);
+    }
+
+    #[test]
+    fn training_never_lowers_the_log_probability() {
+        let observations: Box<[usize]> = (0..300).map(|t| (t * 7 + t / 3) % 5 % 3).collect();
+        let config = TrainingConfig::default().print_progress(false).seed(2).forward_backward_arithmetic(ForwardBackwardArithmetic::LogSpace);
+
+        let mut previous_log_probability = f64::NEG_INFINITY;
+        for iterations in 1..8 {
+            let model = SecondOrderHmmModel::train_model(2, 3, &observations, &config.clone().iterations(iterations)).unwrap();
+            assert!(model.log_probability >= previous_log_probability - 1e-9);
+            previous_log_probability = model.log_probability;
+        }
+    }
+}
@@ -0,0 +1,46 @@
+//! Fixtures and assertions shared by the unit tests.
+
+use crate::{HmmModel, Matrix};
+
+pub(crate) fn matrix(rows: &[&[f64]]) -> Matrix {
+    Matrix::from_rows(&rows.iter().map(|row| row.to_vec()).collect::<Vec<_>>()).unwrap()
+}
+
+/// A model with 2 hidden states and 3 observation symbols, no probability of which is 0.
+pub(crate) fn test_model() -> HmmModel {
+    HmmModel::new(
+        matrix(&[&[0.7, 0.3], &[0.4, 0.6]]),
+        matrix(&[&[0.5, 0.4, 0.1], &[0.1, 0.3, 0.6]]),
+        vec![0.6, 0.4].into_boxed_slice(),
+    )
+}
+
+/// A fixed pseudo-random sequence of symbols below `number_of_observation_symbols`, different for every `seed`.
+pub(crate) fn pseudo_random_observations(number_of_observations: usize, number_of_observation_symbols: usize, seed: u64) -> Box<[usize]> {
+    let mut state = seed;
+    (0..number_of_observations)
+        .map(|_| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 33) as usize % number_of_observation_symbols
+        })
+        .collect()
+}
+
+/// Every sequence of `length` states out of `number_of_hidden_state_symbols`, for checking against brute force.
+pub(crate) fn every_state_path(number_of_hidden_state_symbols: usize, length: usize) -> impl Iterator<Item = Vec<usize>> {
+    (0..number_of_hidden_state_symbols.pow(length as u32)).map(move |path_index| (0..length).map(|t| path_index / number_of_hidden_state_symbols.pow(t as u32) % number_of_hidden_state_symbols).collect())
+}
+
+pub(crate) fn assert_close(actual: &[f64], expected: &[f64], tolerance: f64) {
+    assert_eq!(actual.len(), expected.len());
+    for (&actual_value, &expected_value) in actual.iter().zip(expected.iter()) {
+        assert!((actual_value - expected_value).abs() <= tolerance, "{:?} != {:?}", actual, expected);
+    }
+}
+
+pub(crate) fn assert_matrices_close(actual: &Matrix, expected: &Matrix, tolerance: f64) {
+    assert_eq!(actual.get_number_of_rows(), expected.get_number_of_rows());
+    for (actual_row, expected_row) in actual.rows().zip(expected.rows()) {
+        assert_close(actual_row, expected_row, tolerance);
+    }
+}
@@ -0,0 +1,44 @@
+//! The text preprocessing every experiment trains on, shared with the `score` binary so a model is scored on the
+//! same symbol stream it was trained on.
+
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+/// Lowercase letters and space, in the order of their observation symbols: a, b, ..., z, SPACE => 0, 1, ..., 25, 26.
+pub const LETTERS_AND_SPACE: &str = "abcdefghijklmnopqrstuvwxyz ";
+
+/// Compiled on first use, since `--paragraphs` converts every paragraph separately.
+static EXTRA_WHITESPACE_REGEX: OnceLock<Regex> = OnceLock::new();
+static NON_LETTER_REGEX: OnceLock<Regex> = OnceLock::new();
+
+/// Lowercases `text`, turns every newline and run of whitespace into one space and drops everything but a-z and
+/// space.
+pub fn normalize_text(text: &str) -> String {
+    let lowercase_input = text.to_lowercase();
+    let no_extra_spaces_no_new_lines = EXTRA_WHITESPACE_REGEX
+        .get_or_init(|| Regex::new("(\n\\s*)|(\\s+\\s+)").unwrap())
+        .replace_all(lowercase_input.as_str(), " ")
+        .to_string();
+    NON_LETTER_REGEX.get_or_init(|| Regex::new("[^a-z ]").unwrap()).replace_all(no_extra_spaces_no_new_lines.as_str(), "").into_owned()
+}
+
+/// `normalize_text`, then each character's index in `symbols`. Characters that aren't symbols, e.g. the space for
+/// an alphabet without one, are dropped.
+pub fn text_to_observations(text: &str, symbols: &str) -> Box<[usize]> {
+    let symbols: Box<[char]> = symbols.chars().collect();
+    normalize_text(text).chars().filter_map(|ch| symbols.iter().position(|&symbol| symbol == ch)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn whitespace_collapses_and_everything_else_is_dropped() {
+        // A lone tab isn't a run of whitespace, so it's dropped like punctuation, as it always was in training
+        assert_eq!(normalize_text("The  cat,\n\n   sat.\tOn a mat!"), "the cat saton a mat");
+        assert_eq!(&*text_to_observations("Ab z\n", LETTERS_AND_SPACE), &[0, 1, 26, 25, 26]);
+        assert_eq!(&*text_to_observations("Ab z\n", "abcdefghijklmnopqrstuvwxyz"), &[0, 1, 25]);
+    }
+}
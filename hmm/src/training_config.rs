@@ -0,0 +1,223 @@
+use std::sync::Arc;
+
+use crate::{ForwardBackwardArithmetic, FrozenParameters, Matrix, ProgressPrinter, SilentObserver, TrainingObserver};
+
+/// Hyperparameters for `HmmModel::train_model`.
+///
+/// Built from `TrainingConfig::default()` by chaining setters, e.g.
+/// `TrainingConfig::default().initial_value_range(5.0, 95.0).max_iterations(300)`.
+#[derive(Debug, Clone)]
+pub struct TrainingConfig {
+    pub(crate) min_initial_value: f64,
+    pub(crate) max_initial_value: f64,
+    pub(crate) min_iterations: usize,
+    pub(crate) max_iterations: usize,
+    pub(crate) improvement_threshold: f64,
+    pub(crate) observer: Arc<dyn TrainingObserver>,
+    pub(crate) seed: Option<u64>,
+    pub(crate) state_transition_pseudocount: f64,
+    pub(crate) observation_pseudocount: f64,
+    pub(crate) initial_state_pseudocount: f64,
+    pub(crate) frozen_parameters: FrozenParameters,
+    pub(crate) forward_backward_arithmetic: ForwardBackwardArithmetic,
+    pub(crate) exclude_final_emission: bool,
+    pub(crate) validation_sequence: Option<Arc<[usize]>>,
+    pub(crate) validation_observations: Option<Arc<Matrix>>,
+    pub(crate) validation_patience: usize,
+}
+
+impl Default for TrainingConfig {
+    fn default() -> TrainingConfig {
+        TrainingConfig {
+            min_initial_value: 30.0,
+            max_initial_value: 70.0,
+            min_iterations: 100,
+            max_iterations: 100,
+            improvement_threshold: 0.01,
+            observer: Arc::new(ProgressPrinter),
+            seed: None,
+            state_transition_pseudocount: 0.0,
+            observation_pseudocount: 0.0,
+            initial_state_pseudocount: 0.0,
+            frozen_parameters: FrozenParameters::default(),
+            forward_backward_arithmetic: ForwardBackwardArithmetic::default(),
+            exclude_final_emission: false,
+            validation_sequence: None,
+            validation_observations: None,
+            validation_patience: 5,
+        }
+    }
+}
+
+impl TrainingConfig {
+    /// Initial guesses are drawn uniformly from `min_initial_value..max_initial_value` and then normalized.
+    pub fn initial_value_range(mut self, min_initial_value: f64, max_initial_value: f64) -> TrainingConfig {
+        self.min_initial_value = min_initial_value;
+        self.max_initial_value = max_initial_value;
+        self
+    }
+
+    pub fn min_iterations(mut self, min_iterations: usize) -> TrainingConfig {
+        self.min_iterations = min_iterations;
+        self
+    }
+
+    pub fn max_iterations(mut self, max_iterations: usize) -> TrainingConfig {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    /// Run exactly `iterations` iterations.
+    pub fn iterations(self, iterations: usize) -> TrainingConfig {
+        self.min_iterations(iterations).max_iterations(iterations)
+    }
+
+    /// Training stops once the log probability changes by less than this (after `min_iterations`).
+    pub fn improvement_threshold(mut self, improvement_threshold: f64) -> TrainingConfig {
+        self.improvement_threshold = improvement_threshold;
+        self
+    }
+
+    /// Print the log probability after every iteration (the default), or nothing. Replaces any observer set before.
+    pub fn print_progress(self, print_progress: bool) -> TrainingConfig {
+        if print_progress {
+            self.observer(Arc::new(ProgressPrinter))
+        } else {
+            self.observer(Arc::new(SilentObserver))
+        }
+    }
+
+    /// Send training events to `observer` instead of printing them.
+    pub fn observer(mut self, observer: Arc<dyn TrainingObserver>) -> TrainingConfig {
+        self.observer = observer;
+        self
+    }
+
+    /// Seed for the random initial guesses, so a run can be replayed exactly.
+    /// Without one a fresh seed is drawn and recorded in `HmmModel::seed`.
+    pub fn seed(mut self, seed: u64) -> TrainingConfig {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Added to every expected transition count before A is re-estimated.
+    ///
+    /// This is MAP estimation under a symmetric Dirichlet prior with concentration `pseudocount + 1`, so a
+    /// transition that's never seen keeps a small probability and a state that's never visited gets a uniform row
+    /// instead of NaN. 0 (the default) is plain maximum likelihood.
+    pub fn state_transition_pseudocount(mut self, state_transition_pseudocount: f64) -> TrainingConfig {
+        self.state_transition_pseudocount = state_transition_pseudocount;
+        self
+    }
+
+    /// Like `state_transition_pseudocount`, for the expected emission counts behind B.
+    pub fn observation_pseudocount(mut self, observation_pseudocount: f64) -> TrainingConfig {
+        self.observation_pseudocount = observation_pseudocount;
+        self
+    }
+
+    /// Like `state_transition_pseudocount`, for the expected initial state counts behind pi.
+    pub fn initial_state_pseudocount(mut self, initial_state_pseudocount: f64) -> TrainingConfig {
+        self.initial_state_pseudocount = initial_state_pseudocount;
+        self
+    }
+
+    /// Use the same pseudocount for A, B and pi.
+    pub fn pseudocount(self, pseudocount: f64) -> TrainingConfig {
+        self.state_transition_pseudocount(pseudocount)
+            .observation_pseudocount(pseudocount)
+            .initial_state_pseudocount(pseudocount)
+    }
+
+    /// Keep these parameter blocks fixed instead of guessing and re-estimating them.
+    pub fn frozen_parameters(mut self, frozen_parameters: FrozenParameters) -> TrainingConfig {
+        self.frozen_parameters = frozen_parameters;
+        self
+    }
+
+    /// Run the E-step's forward-backward with scale factors (the default) or in log space.
+    pub fn forward_backward_arithmetic(mut self, forward_backward_arithmetic: ForwardBackwardArithmetic) -> TrainingConfig {
+        self.forward_backward_arithmetic = forward_backward_arithmetic;
+        self
+    }
+
+    /// Leave the last observation of every sequence out of the B update, as training did before it counted
+    /// t = 0..T-1. Only for reproducing models trained that way.
+    pub fn exclude_final_emission(mut self, exclude_final_emission: bool) -> TrainingConfig {
+        self.exclude_final_emission = exclude_final_emission;
+        self
+    }
+
+    /// Score the parameters from every M-step on `validation_sequence` and return the ones that scored best, stopping
+    /// once `validation_patience` iterations in a row haven't improved on that (but never before `min_iterations`).
+    /// The random initial guess is never a candidate.
+    pub fn validation_sequence(mut self, validation_sequence: &[usize]) -> TrainingConfig {
+        self.validation_sequence = Some(validation_sequence.into());
+        self
+    }
+
+    /// Like `validation_sequence`, for a `GaussianHmmModel`, whose observations are rows of real values.
+    pub fn validation_observations(mut self, validation_observations: &Matrix) -> TrainingConfig {
+        self.validation_observations = Some(Arc::new(validation_observations.clone()));
+        self
+    }
+
+    /// Iterations in a row without a better validation score before training stops (default 5, at least 1).
+    pub fn validation_patience(mut self, validation_patience: usize) -> TrainingConfig {
+        self.validation_patience = validation_patience.max(1);
+        self
+    }
+
+    pub fn get_initial_value_range(&self) -> (f64, f64) {
+        (self.min_initial_value, self.max_initial_value)
+    }
+
+    pub fn get_min_iterations(&self) -> usize {
+        self.min_iterations
+    }
+
+    pub fn get_max_iterations(&self) -> usize {
+        self.max_iterations
+    }
+
+    pub fn get_improvement_threshold(&self) -> f64 {
+        self.improvement_threshold
+    }
+
+    pub fn get_seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    /// Pseudocounts for A, B and pi, in that order.
+    pub fn get_pseudocounts(&self) -> (f64, f64, f64) {
+        (self.state_transition_pseudocount, self.observation_pseudocount, self.initial_state_pseudocount)
+    }
+
+    pub fn get_frozen_parameters(&self) -> &FrozenParameters {
+        &self.frozen_parameters
+    }
+
+    pub fn get_observer(&self) -> &Arc<dyn TrainingObserver> {
+        &self.observer
+    }
+
+    pub fn get_forward_backward_arithmetic(&self) -> ForwardBackwardArithmetic {
+        self.forward_backward_arithmetic
+    }
+
+    pub fn get_exclude_final_emission(&self) -> bool {
+        self.exclude_final_emission
+    }
+
+    pub fn get_validation_sequence(&self) -> Option<&[usize]> {
+        self.validation_sequence.as_deref()
+    }
+
+    pub fn get_validation_observations(&self) -> Option<&Matrix> {
+        self.validation_observations.as_deref()
+    }
+
+    pub fn get_validation_patience(&self) -> usize {
+        self.validation_patience
+    }
+}
@@ -0,0 +1,114 @@
+use crate::{HmmError, HmmModel, Matrix};
+
+/// Most likely hidden state sequence for an observation sequence.
+#[derive(Debug, Clone)]
+pub struct ViterbiPath {
+    pub state_path: Box<[usize]>,
+    /// log2 P(X, O | model) of `state_path`.
+    pub log_probability: f64,
+}
+
+impl HmmModel {
+    /// Finds the most likely hidden state sequence for `observations` with the Viterbi algorithm.
+    ///
+    /// Works with log2 probabilities so long sequences don't underflow. Every path of an impossible sequence has
+    /// probability 0, so like `posterior_decode` it's an error.
+    pub fn viterbi(&self, observations: &[usize]) -> Result<ViterbiPath, HmmError> {
+        find_viterbi_path(&self.state_transition_matrix, &self.initial_state_distribution_vector, &self.emission_probability_matrix(observations)?)
+    }
+}
+
+/// Viterbi on an emission probability matrix whose row t holds b_i(O_t) for every state i, shared by every
+/// kind of emission. Fails if no path has a probability above 0.
+pub(crate) fn find_viterbi_path(state_transition_matrix: &Matrix, initial_state_distribution_vector: &[f64], emission_probability_matrix: &Matrix) -> Result<ViterbiPath, HmmError> {
+    let number_of_hidden_state_symbols = initial_state_distribution_vector.len();
+    let number_of_observations = emission_probability_matrix.get_number_of_rows();
+    if number_of_observations == 0 {
+        return Ok(ViterbiPath {
+            state_path: Vec::new().into_boxed_slice(),
+            log_probability: 0.0,
+        });
+    }
+
+    // delta_0(i) = log(pi_i * b_i(O_0))
+    let mut delta_vector: Box<[f64]> = (0..number_of_hidden_state_symbols)
+        .map(|i| initial_state_distribution_vector[i].log2() + emission_probability_matrix[0][i].log2())
+        .collect();
+    let mut back_pointer_matrix = vec![vec![0; number_of_hidden_state_symbols].into_boxed_slice(); number_of_observations].into_boxed_slice();
+
+    for (observation_index, observation_probability_vector) in emission_probability_matrix.rows().enumerate().skip(1) {
+        let mut new_delta_vector = vec![f64::NEG_INFINITY; number_of_hidden_state_symbols].into_boxed_slice();
+        for (i, &observation_probability) in observation_probability_vector.iter().enumerate() {
+            // delta_t(i) = max_j(delta_t-1(j) + log(a_ji)) + log(b_i(O_t))
+            let mut best_previous_state = 0;
+            let mut best_log_probability = f64::NEG_INFINITY;
+            for (j, &previous_delta) in delta_vector.iter().enumerate() {
+                let log_probability = previous_delta + state_transition_matrix[j][i].log2();
+                if log_probability > best_log_probability {
+                    best_previous_state = j;
+                    best_log_probability = log_probability;
+                }
+            }
+
+            back_pointer_matrix[observation_index][i] = best_previous_state;
+            new_delta_vector[i] = best_log_probability + observation_probability.log2();
+        }
+
+        delta_vector = new_delta_vector;
+    }
+
+    let mut final_state = 0;
+    for (i, &delta) in delta_vector.iter().enumerate() {
+        if delta > delta_vector[final_state] {
+            final_state = i;
+        }
+    }
+    if delta_vector[final_state] == f64::NEG_INFINITY {
+        return Err(HmmError::ImpossibleObservations);
+    }
+
+    // Follow the back pointers from T-1 to 0
+    let mut state_path = vec![final_state; number_of_observations].into_boxed_slice();
+    for observation_index in (1..number_of_observations).rev() {
+        state_path[observation_index - 1] = back_pointer_matrix[observation_index][state_path[observation_index]];
+    }
+
+    Ok(ViterbiPath {
+        state_path,
+        log_probability: delta_vector[final_state],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{every_state_path, matrix, test_model};
+
+    #[test]
+    fn viterbi_finds_the_most_likely_path() {
+        let model = test_model();
+        for observations in [&[2][..], &[0, 1], &[2, 0, 1, 1, 2, 0], &[1, 1, 1, 0, 2, 2, 0, 1]] {
+            let (best_log_probability, best_state_path) = every_state_path(2, observations.len())
+                .map(|state_path| {
+                    let mut log_probability = (model.initial_state_distribution_vector[state_path[0]] * model.observation_probability_matrix[state_path[0]][observations[0]]).log2();
+                    for t in 1..observations.len() {
+                        log_probability += (model.state_transition_matrix[state_path[t - 1]][state_path[t]] * model.observation_probability_matrix[state_path[t]][observations[t]]).log2();
+                    }
+                    (log_probability, state_path)
+                })
+                .fold((f64::NEG_INFINITY, Vec::new()), |best, candidate| if candidate.0 > best.0 { candidate } else { best });
+
+            let viterbi_path = model.viterbi(observations).unwrap();
+            assert_eq!(&*viterbi_path.state_path, &best_state_path[..]);
+            assert!((viterbi_path.log_probability - best_log_probability).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn an_impossible_sequence_has_no_viterbi_path() {
+        let model = HmmModel::new(matrix(&[&[1.0, 0.0], &[0.0, 1.0]]), matrix(&[&[0.5, 0.5, 0.0], &[0.5, 0.5, 0.0]]), vec![0.5, 0.5].into_boxed_slice());
+
+        assert!(matches!(model.viterbi(&[0, 2, 1]), Err(HmmError::ImpossibleObservations)));
+        assert!(matches!(model.posterior_decode(&[0, 2, 1]), Err(HmmError::ImpossibleObservations)));
+    }
+}